@@ -148,20 +148,20 @@ const MANDLEBROT_SOURCE_BF: &str =
 
 fn main() {
     let t0 = time::Instant::now();
-    let mut bytecode = Compiler::compile(MANDLEBROT_SOURCE_BF);
-    let mut memory = vec![0u8; 1024];
-    Interpreter::new()
+    let mut bytecode = Compiler::compile(MANDLEBROT_SOURCE_BF).expect("mandelbrot source should compile");
+    Interpreter::<u8>::new()
         .with_instruction_buffer(&mut bytecode)
-        .with_memory(&mut memory)
-        .run();
+        .with_growable_memory(TapeGrowth::Right)
+        .run()
+        .expect("mandelbrot should run to completion");
     println!("optimized version took '{}' seconds" ,t0.elapsed().as_secs());
 
     let t0 = time::Instant::now();
-    let mut bytecode = Compiler::compile_unoptimized(MANDLEBROT_SOURCE_BF);
-    let mut memory = vec![0u8; 1024];
-    Interpreter::new()
+    let mut bytecode = Compiler::compile_unoptimized(MANDLEBROT_SOURCE_BF).expect("mandelbrot source should compile");
+    Interpreter::<u8>::new()
         .with_instruction_buffer(&mut bytecode)
-        .with_memory(&mut memory)
-        .run();
+        .with_growable_memory(TapeGrowth::Right)
+        .run()
+        .expect("mandelbrot should run to completion");
     println!("unoptimized version took '{}' seconds" ,t0.elapsed().as_secs());
 }
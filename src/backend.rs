@@ -0,0 +1,71 @@
+use super::*;
+mod asm;
+mod c;
+mod elf;
+mod rust;
+pub(crate) mod x86;
+pub use asm::*;
+pub use c::*;
+pub use elf::*;
+pub use rust::*;
+
+/// Cells a generated program's tape has unless told otherwise
+pub const DEFAULT_TAPE_LEN: usize = 30_000;
+
+/// Turns compiled bytecode into a program for some other toolchain to build
+pub trait Backend {
+    /// writes the translation of `code` to `output`
+    fn emit(&self, code: &[ByteCode], output: &mut dyn Write) -> io::Result<()>;
+
+    /// [`Backend::emit`] into a string
+    fn emit_to_string(&self, code: &[ByteCode]) -> String {
+        let mut output = Vec::new();
+        self.emit(code, &mut output)
+            .expect("writing to a Vec can't fail");
+        String::from_utf8(output).expect("backends emit text")
+    }
+}
+
+/// runs the program at `path` with `input` on stdin, giving back its exit status and stdout
+#[cfg(test)]
+fn run_program(path: &std::path::Path, input: &[u8]) -> (Option<i32>, Vec<u8>) {
+    use std::process::{Command, Stdio};
+    let mut child = loop {
+        match Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            //another test forking while the file was open for writing keeps it busy for a moment
+            Err(err) if err.kind() == io::ErrorKind::ExecutableFileBusy => continue,
+            child => break child.unwrap(),
+        }
+    };
+    //programs that stop before reading everything close the pipe early
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let run = child.wait_with_output().unwrap();
+    (run.status.code(), run.stdout)
+}
+
+/// what a generated program should do given `input`: the interpreter's output, and exit status 1
+/// if it ran into an error
+#[cfg(test)]
+fn run_interpreter<C: Cell>(
+    code: &[ByteCode],
+    tape_len: usize,
+    eof_policy: EofPolicy,
+    input: &[u8],
+) -> (Option<i32>, Vec<u8>) {
+    let mut bytecode = code.to_vec();
+    let mut memory = vec![C::ZERO; tape_len];
+    let mut output = Vec::new();
+    let outcome = Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_eof_policy(eof_policy)
+        .with_input(input)
+        .with_output(&mut output)
+        .run();
+    (Some(if outcome.is_ok() { 0 } else { 1 }), output)
+}
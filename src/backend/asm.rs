@@ -0,0 +1,387 @@
+use super::elf::{CodeGen, Layout, EOF_MESSAGE, OUTPUT_BUFFER_LEN, OUT_OF_BOUNDS_MESSAGE};
+use super::x86::{Arg, Assembler, Insn, Label, Line, Mem, Width};
+use super::*;
+use std::{collections::HashSet, marker::PhantomData};
+
+/// Which assembler [`AsmBackend`] writes for
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum AsmSyntax {
+    /// AT&T syntax for the GNU assembler, `addb $3, (%r12)`
+    #[default]
+    Att,
+    /// Intel syntax for NASM, `add byte [r12], 3`
+    Intel,
+}
+
+/// Prints the x86-64 code [`ElfBackend`] generates as assembly source, so you can read how
+/// each instruction of the optimized bytecode turns into machine instructions. The code for
+/// every instruction starts with a comment naming it and, given a [`SourceMap`], the part of the
+/// source it was compiled from.
+///
+/// The output is a complete Linux program that behaves like the executable [`ElfBackend`]
+/// writes, build it with `as prog.s -o prog.o && ld prog.o -o prog`, or with
+/// `nasm -f elf64 prog.asm && ld prog.o -o prog` for [`AsmSyntax::Intel`]
+pub struct AsmBackend<C: Cell = u8> {
+    tape_len: usize,
+    eof_policy: EofPolicy,
+    syntax: AsmSyntax,
+    source_map: Option<SourceMap>,
+    cell: PhantomData<C>,
+}
+
+impl<C: Cell> Default for AsmBackend<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Cell> AsmBackend<C> {
+    pub fn new() -> Self {
+        Self {
+            tape_len: DEFAULT_TAPE_LEN,
+            eof_policy: EofPolicy::default(),
+            syntax: AsmSyntax::default(),
+            source_map: None,
+            cell: PhantomData,
+        }
+    }
+
+    pub fn with_tape_len(mut self, tape_len: usize) -> Self {
+        self.tape_len = tape_len;
+        self
+    }
+
+    /// what `,` does at the end of input. The code is the same [`ElfBackend`] generates, so
+    /// [`EofPolicy::Error`] ends the program with a message on stderr and exit status 1
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    pub fn with_syntax(mut self, syntax: AsmSyntax) -> Self {
+        self.syntax = syntax;
+        self
+    }
+
+    /// the source map the bytecode was compiled with, to note where each instruction came from
+    pub fn with_source_map(mut self, source_map: SourceMap) -> Self {
+        self.source_map = Some(source_map);
+        self
+    }
+
+    fn comment(&self) -> &'static str {
+        match self.syntax {
+            AsmSyntax::Att => "#",
+            AsmSyntax::Intel => ";",
+        }
+    }
+
+    /// the tape, buffers and messages the code refers to by name, then the start of the code
+    fn emit_sections(&self, layout: &Layout, output: &mut dyn Write) -> io::Result<()> {
+        let messages = [
+            ("out_of_bounds_message", OUT_OF_BOUNDS_MESSAGE),
+            ("eof_message", EOF_MESSAGE),
+        ];
+        let buffers = [
+            ("tape", layout.tape_bytes),
+            ("tape_end", 0),
+            ("output_buffer", OUTPUT_BUFFER_LEN),
+            ("output_end", 0),
+            ("scratch", 1),
+        ];
+        let (global, rodata, bss, text) = match self.syntax {
+            AsmSyntax::Att => ("\t.globl _start", "\t.section .rodata", "\t.bss", "\t.text"),
+            AsmSyntax::Intel => (
+                "\tglobal _start",
+                "\tsection .rodata",
+                "\tsection .bss",
+                "\tsection .text",
+            ),
+        };
+        writeln!(output, "{}\n{}", global, rodata)?;
+        for (name, message) in messages {
+            writeln!(output, "{}:", name)?;
+            match self.syntax {
+                AsmSyntax::Att => writeln!(output, "\t.ascii \"{}\"", escape(message))?,
+                AsmSyntax::Intel => writeln!(output, "\tdb `{}`", escape(message))?,
+            }
+        }
+        writeln!(output, "{}", bss)?;
+        for (name, len) in buffers {
+            writeln!(output, "{}:", name)?;
+            if len > 0 {
+                match self.syntax {
+                    AsmSyntax::Att => writeln!(output, "\t.zero {}", len)?,
+                    AsmSyntax::Intel => writeln!(output, "\tresb {}", len)?,
+                }
+            }
+        }
+        writeln!(output, "{}\n_start:", text)
+    }
+
+    fn label(&self, asm: &Assembler, label: Label) -> String {
+        match (asm.name(label), self.syntax) {
+            (Some(name), _) => String::from(name),
+            (None, AsmSyntax::Att) => format!(".L{}", label.index()),
+            (None, AsmSyntax::Intel) => format!("L{}", label.index()),
+        }
+    }
+
+    fn instruction(&self, asm: &Assembler, insn: &Insn) -> String {
+        //only operands without a register need their size spelled out
+        let sized = !insn.args.iter().any(|arg| matches!(arg, Arg::Reg(..)));
+        match self.syntax {
+            AsmSyntax::Att => {
+                let mnemonic = match insn.args[..] {
+                    [Arg::Reg(_, dst), Arg::Mem(_, src)] if insn.mnemonic == "movzx" => {
+                        format!("movz{}{}", suffix(src), suffix(dst))
+                    }
+                    _ => match insn.args.iter().find_map(|arg| match arg {
+                        Arg::Mem(_, width) if sized => Some(*width),
+                        _ => None,
+                    }) {
+                        Some(width) => format!("{}{}", insn.mnemonic, suffix(width)),
+                        None => String::from(insn.mnemonic),
+                    },
+                };
+                let indirect = matches!(insn.mnemonic, "call" | "jmp");
+                let args = insn.args.iter().rev().map(|&arg| match arg {
+                    Arg::Reg(reg, width) if indirect => format!("*%{}", reg.name(width)),
+                    Arg::Reg(reg, width) => format!("%{}", reg.name(width)),
+                    Arg::Mem(Mem { base, disp: 0 }, _) => format!("(%{})", base.name(Width::Qword)),
+                    Arg::Mem(Mem { base, disp }, _) => {
+                        format!("{}(%{})", disp, base.name(Width::Qword))
+                    }
+                    Arg::Imm(value) => format!("${}", immediate(value)),
+                    Arg::Symbol(symbol) => format!("${}", symbol),
+                    Arg::Label(label) => self.label(asm, label),
+                });
+                operands(mnemonic, args)
+            }
+            AsmSyntax::Intel => {
+                let sized = sized || insn.mnemonic == "movzx";
+                let args = insn.args.iter().map(|&arg| match arg {
+                    Arg::Reg(reg, width) => String::from(reg.name(width)),
+                    Arg::Mem(Mem { base, disp }, width) => {
+                        let size = match (sized, width) {
+                            (false, _) => "",
+                            (true, Width::Byte) => "byte ",
+                            (true, Width::Word) => "word ",
+                            (true, Width::Dword) => "dword ",
+                            (true, Width::Qword) => "qword ",
+                        };
+                        let base = base.name(Width::Qword);
+                        match disp {
+                            0 => format!("{}[{}]", size, base),
+                            disp if disp < 0 => format!("{}[{} - {}]", size, base, -(disp as i64)),
+                            disp => format!("{}[{} + {}]", size, base, disp),
+                        }
+                    }
+                    Arg::Imm(value) => immediate(value),
+                    Arg::Symbol(symbol) => String::from(symbol),
+                    Arg::Label(label) => self.label(asm, label),
+                });
+                operands(String::from(insn.mnemonic), args)
+            }
+        }
+    }
+}
+
+impl<C: Cell> Backend for AsmBackend<C> {
+    fn emit(&self, code: &[ByteCode], output: &mut dyn Write) -> io::Result<()> {
+        let layout = Layout::new::<C>(self.tape_len)?;
+        let asm =
+            CodeGen::<C>::new(Assembler::with_listing(), code, layout, self.eof_policy).assemble();
+        let listing = asm.listing().expect("the assembler keeps a listing");
+        let targets: HashSet<Label> = listing
+            .iter()
+            .flat_map(|line| match line {
+                Line::Insn(insn) => insn.args.as_slice(),
+                _ => &[],
+            })
+            .filter_map(|arg| match arg {
+                Arg::Label(label) => Some(*label),
+                _ => None,
+            })
+            .collect();
+
+        let comment = self.comment();
+        writeln!(
+            output,
+            "{} {} cells of {} bits, built with `{}`",
+            comment,
+            self.tape_len,
+            C::BITS,
+            match self.syntax {
+                AsmSyntax::Att => "as prog.s -o prog.o && ld prog.o -o prog",
+                AsmSyntax::Intel => "nasm -f elf64 prog.asm && ld prog.o -o prog",
+            }
+        )?;
+        self.emit_sections(&layout, output)?;
+        for line in listing {
+            match line {
+                Line::Mark(ip) => {
+                    let span = self.source_map.as_ref().and_then(|map| map.span(*ip));
+                    match span {
+                        Some(span) => writeln!(
+                            output,
+                            "{} {:>6}  {:<12} {:?}",
+                            comment,
+                            ip,
+                            span.to_string(),
+                            code[*ip]
+                        )?,
+                        None => writeln!(output, "{} {:>6}  {:?}", comment, ip, code[*ip])?,
+                    }
+                }
+                Line::Bind(label) if asm.name(*label).is_some() => {
+                    writeln!(output, "\n{}:", self.label(&asm, *label))?
+                }
+                Line::Bind(label) if targets.contains(label) => {
+                    writeln!(output, "{}:", self.label(&asm, *label))?
+                }
+                Line::Bind(_) => {}
+                Line::Insn(insn) => writeln!(output, "\t{}", self.instruction(&asm, insn))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// the AT&T mnemonic suffix for an operand size
+fn suffix(width: Width) -> char {
+    match width {
+        Width::Byte => 'b',
+        Width::Word => 'w',
+        Width::Dword => 'l',
+        Width::Qword => 'q',
+    }
+}
+
+/// small numbers in decimal, addresses and masks in hex
+fn immediate(value: i64) -> String {
+    match value.unsigned_abs() {
+        magnitude if magnitude < 0x1_0000 => value.to_string(),
+        magnitude if value < 0 => format!("-{:#x}", magnitude),
+        magnitude => format!("{:#x}", magnitude),
+    }
+}
+
+fn operands(mnemonic: String, args: impl Iterator<Item = String>) -> String {
+    let args: Vec<_> = args.collect();
+    if args.is_empty() {
+        mnemonic
+    } else {
+        format!("{} {}", mnemonic, args.join(", "))
+    }
+}
+
+/// `bytes` as the inside of a string literal both assemblers read C escapes in
+fn escape(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .flat_map(|byte| byte.escape_ascii())
+        .map(char::from)
+        .collect()
+}
+
+#[test]
+fn emits_assembly() {
+    let (code, source_map) = Compiler::compile_with_source_map("+[->+<]>.").unwrap();
+    let att = AsmBackend::<u16>::new()
+        .with_source_map(source_map.clone())
+        .emit_to_string(&code);
+    assert!(att.contains("tape:\n\t.zero 60000\n"));
+    assert!(att.contains("#      1  1:2-1:7      MultiplyAdd"));
+    assert!(att.contains("\tmovzwl (%r12), %eax\n"));
+    assert!(att.contains("\tmovw $0, (%r12)\n"));
+    assert!(att.contains("\tcall put\n"));
+    assert!(att.contains("\tmov $output_buffer, %r15d\n"));
+
+    let intel = AsmBackend::<u16>::new()
+        .with_syntax(AsmSyntax::Intel)
+        .with_source_map(source_map)
+        .emit_to_string(&code);
+    assert!(intel.contains("tape:\n\tresb 60000\n"));
+    assert!(intel.contains(";      1  1:2-1:7      MultiplyAdd"));
+    assert!(intel.contains("\tmovzx eax, word [r12]\n"));
+    assert!(intel.contains("\tmov word [r12], 0\n"));
+    assert!(intel.contains("\tdb `data pointer moved off the tape\\n`\n"));
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        use std::process::Command;
+        //false if the tool isn't installed
+        let tool = |command: &mut Command| match command.output() {
+            Ok(done) => {
+                assert!(
+                    done.status.success(),
+                    "{}",
+                    String::from_utf8_lossy(&done.stderr)
+                );
+                true
+            }
+            Err(_) => false,
+        };
+        let scan = ",[->++>+++<<]>.>.[<]>>[>]<.,.,.";
+        let cases = [
+            ("+[->+<]>.", EofPolicy::Zero, &b""[..]),
+            (scan, EofPolicy::Max, b"\x05"),
+            (scan, EofPolicy::Unchanged, b"\x05"),
+            (",.,.", EofPolicy::Error, b"a"),
+            ("+.<.", EofPolicy::Zero, b""),
+        ];
+        let dir =
+            std::env::temp_dir().join(format!("brainfrusk-asm-backend-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, (source, eof_policy, input)) in cases.into_iter().enumerate() {
+            let code = Compiler::compile(source).unwrap();
+            let elf = dir.join(format!("elf{}", i));
+            let executable = ElfBackend::<u16>::new()
+                .with_tape_len(100)
+                .with_eof_policy(eof_policy)
+                .executable(&code)
+                .unwrap();
+            std::fs::write(&elf, executable).unwrap();
+            std::fs::set_permissions(&elf, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let expected = run_program(&elf, input);
+
+            let backend = AsmBackend::<u16>::new()
+                .with_tape_len(100)
+                .with_eof_policy(eof_policy);
+            let (path, object, binary) = (
+                dir.join(format!("att{}.s", i)),
+                dir.join(format!("att{}.o", i)),
+                dir.join(format!("att{}", i)),
+            );
+            std::fs::write(&path, backend.emit_to_string(&code)).unwrap();
+            if tool(Command::new("as").arg(&path).arg("-o").arg(&object))
+                && tool(Command::new("ld").arg(&object).arg("-o").arg(&binary))
+            {
+                assert_eq!(run_program(&binary, input), expected, "{}", source);
+            }
+
+            //the GNU assembler's Intel mode wants `byte ptr` and `offset` where NASM doesn't,
+            //so the Intel output goes unchecked unless NASM is installed
+            let (path, object, binary) = (
+                dir.join(format!("intel{}.asm", i)),
+                dir.join(format!("intel{}.o", i)),
+                dir.join(format!("intel{}", i)),
+            );
+            let intel = backend.with_syntax(AsmSyntax::Intel);
+            std::fs::write(&path, intel.emit_to_string(&code)).unwrap();
+            if tool(
+                Command::new("nasm")
+                    .args(["-f", "elf64"])
+                    .arg(&path)
+                    .arg("-o")
+                    .arg(&object),
+            ) && tool(Command::new("ld").arg(&object).arg("-o").arg(&binary))
+            {
+                assert_eq!(run_program(&binary, input), expected, "{}", source);
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
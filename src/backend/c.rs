@@ -0,0 +1,212 @@
+use super::*;
+use std::marker::PhantomData;
+
+/// Translates bytecode into a self-contained C program that reads stdin and writes stdout.
+///
+/// The tape is a fixed array of `C` cells that starts at its leftmost cell. Cells are the
+/// unsigned `uint*_t` of the same width, which C defines to wrap, and the pointer isn't bounds
+/// checked, so programs that leave the tape are undefined behaviour rather than a
+/// [`RuntimeError`]
+pub struct CBackend<C: Cell = u8> {
+    tape_len: usize,
+    eof_policy: EofPolicy,
+    cell: PhantomData<C>,
+}
+
+impl<C: Cell> Default for CBackend<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Cell> CBackend<C> {
+    pub fn new() -> Self {
+        Self {
+            tape_len: DEFAULT_TAPE_LEN,
+            eof_policy: EofPolicy::default(),
+            cell: PhantomData,
+        }
+    }
+
+    pub fn with_tape_len(mut self, tape_len: usize) -> Self {
+        self.tape_len = tape_len;
+        self
+    }
+
+    /// what `,` does once `getchar` returns `EOF`. [`EofPolicy::Unchanged`] returns before
+    /// touching the cell and [`EofPolicy::Error`] prints a message to stderr and calls `exit(1)`
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    fn emit_prelude(&self, output: &mut dyn Write) -> io::Result<()> {
+        let on_eof = match self.eof_policy {
+            EofPolicy::Zero => "*target = 0;",
+            EofPolicy::Max => "*target = (cell)~(cell)0;",
+            EofPolicy::Unchanged => "return;",
+            EofPolicy::Error => "fputs(\"unexpected end of input\\n\", stderr);\n        exit(1);",
+        };
+        writeln!(output, "#include <stdint.h>")?;
+        writeln!(output, "#include <stdio.h>")?;
+        writeln!(output, "#include <stdlib.h>")?;
+        writeln!(output)?;
+        writeln!(output, "typedef uint{}_t cell;", C::BITS)?;
+        writeln!(output, "typedef unsigned long long wide;")?;
+        writeln!(output)?;
+        writeln!(output, "static cell tape[{}];", self.tape_len)?;
+        writeln!(output)?;
+        writeln!(output, "static inline void input(cell *target) {{")?;
+        writeln!(output, "    fflush(stdout);")?;
+        writeln!(output, "    int byte = getchar();")?;
+        writeln!(output, "    if (byte == EOF) {{")?;
+        writeln!(output, "        {}", on_eof)?;
+        writeln!(output, "    }} else {{")?;
+        writeln!(output, "        *target = (cell)byte;")?;
+        writeln!(output, "    }}")?;
+        writeln!(output, "}}")?;
+        writeln!(output)?;
+        writeln!(output, "int main(void) {{")?;
+        writeln!(output, "    cell *p = tape;")?;
+        Ok(())
+    }
+
+    /// the C statement for a single instruction, `None` for the ones that open or close a block
+    fn statement(inst: ByteCode) -> Option<String> {
+        let statement = match inst {
+            ByteCode::NOP => return Some(String::new()),
+            ByteCode::IncrementDataPtr => String::from("p += 1;"),
+            ByteCode::DecrementDataPtr => String::from("p -= 1;"),
+            ByteCode::MoveDataPtr(amount) => format!("p += {};", amount),
+            ByteCode::IncrementByte => String::from("*p += 1;"),
+            ByteCode::DecrementByte => String::from("*p -= 1;"),
+            ByteCode::AddByte(delta) => format!("*p += (cell){}LL;", delta),
+            ByteCode::AddByteAt { offset, delta } => {
+                format!("p[{}] += (cell){}LL;", offset, delta)
+            }
+            ByteCode::SetByte(value) => format!("*p = (cell){}LL;", value),
+            ByteCode::SetByteAt { offset, value } => {
+                format!("p[{}] = (cell){}LL;", offset, value)
+            }
+            ByteCode::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => format!(
+                "if (p[{1}]) p[{0}] += (cell)((wide)p[{1}] * (wide){2}LL);",
+                offset, source, factor
+            ),
+            ByteCode::Scan(stride) => format!("while (*p) p += {};", stride),
+            ByteCode::OutputByte => String::from("putchar((unsigned char)*p);"),
+            ByteCode::OutputByteAt(offset) => format!("putchar((unsigned char)p[{}]);", offset),
+            ByteCode::OutputConst(byte) => format!("putchar({});", byte),
+            ByteCode::InputByte => String::from("input(p);"),
+            ByteCode::InputByteAt(offset) => format!("input(&p[{}]);", offset),
+            ByteCode::LoopOpen { .. } | ByteCode::LoopClose { .. } => return None,
+        };
+        Some(statement)
+    }
+}
+
+impl<C: Cell> Backend for CBackend<C> {
+    fn emit(&self, code: &[ByteCode], output: &mut dyn Write) -> io::Result<()> {
+        self.emit_prelude(output)?;
+        let mut depth = 1;
+        for &inst in code {
+            match inst {
+                ByteCode::LoopOpen { .. } => {
+                    writeln!(output, "{:indent$}while (*p) {{", "", indent = depth * 4)?;
+                    depth += 1;
+                }
+                ByteCode::LoopClose { .. } => {
+                    depth -= 1;
+                    writeln!(output, "{:indent$}}}", "", indent = depth * 4)?;
+                }
+                _ => match Self::statement(inst) {
+                    Some(statement) if !statement.is_empty() => {
+                        writeln!(output, "{:indent$}{}", "", statement, indent = depth * 4)?;
+                    }
+                    _ => {}
+                },
+            }
+        }
+        //folded programs may never touch the tape
+        writeln!(output, "    (void)p;")?;
+        writeln!(output, "    fflush(stdout);")?;
+        writeln!(output, "    return 0;")?;
+        writeln!(output, "}}")
+    }
+}
+
+#[test]
+fn emits_a_c_program() {
+    let code = Compiler::compile(",[->+<]>.").unwrap();
+    let program = CBackend::<u16>::new()
+        .with_tape_len(64)
+        .with_eof_policy(EofPolicy::Max)
+        .emit_to_string(&code);
+    assert!(program.contains("typedef uint16_t cell;"));
+    assert!(program.contains("static cell tape[64];"));
+    assert!(program.contains("*target = (cell)~(cell)0;"));
+    assert!(program.contains("if (p[0]) p[1] += (cell)((wide)p[0] * (wide)1LL);"));
+    assert!(program.contains("putchar((unsigned char)p[1]);"));
+    assert_eq!(program.matches('{').count(), program.matches('}').count());
+
+    //needs a C compiler to go any further
+    let cc = option_env!("CC").unwrap_or("cc");
+    if std::process::Command::new(cc)
+        .arg("--version")
+        .output()
+        .is_err()
+    {
+        return;
+    }
+    let source = ",[->++>+++<<]>.>.[<]>>[>]<.,.,.";
+    let code = Compiler::compile(source).unwrap();
+    assert!(code
+        .iter()
+        .any(|inst| matches!(inst, ByteCode::MultiplyAdd { .. })));
+    assert!(code
+        .iter()
+        .any(|inst| matches!(inst, ByteCode::Scan { .. })));
+    let dir = std::env::temp_dir().join(format!("brainfrusk-c-backend-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let policies = [
+        EofPolicy::Zero,
+        EofPolicy::Max,
+        EofPolicy::Unchanged,
+        EofPolicy::Error,
+    ];
+    for (i, eof_policy) in policies.into_iter().enumerate() {
+        let program = CBackend::<u16>::new()
+            .with_tape_len(64)
+            .with_eof_policy(eof_policy)
+            .emit_to_string(&code);
+        let (path, binary) = (
+            dir.join(format!("main{}.c", i)),
+            dir.join(format!("main{}", i)),
+        );
+        std::fs::write(&path, program).unwrap();
+        let build = std::process::Command::new(cc)
+            .arg(&path)
+            .arg("-o")
+            .arg(&binary)
+            .output()
+            .unwrap();
+        assert!(
+            build.status.success(),
+            "{}",
+            String::from_utf8_lossy(&build.stderr)
+        );
+        for input in [&b"\x05"[..], b"\xff\x07"] {
+            assert_eq!(
+                run_program(&binary, input),
+                run_interpreter::<u16>(&code, 64, eof_policy, input),
+                "{:?} with {:?}",
+                input,
+                eof_policy
+            );
+        }
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
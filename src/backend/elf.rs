@@ -0,0 +1,509 @@
+use super::x86::{Assembler, Cond, Label, Mem, Reg, Width};
+use super::*;
+use std::marker::PhantomData;
+
+/// Where the headers, messages and code get mapped
+const TEXT_ADDRESS: u64 = 0x40_0000;
+/// Where the tape and output buffer get mapped, far enough from the code for any program
+const BSS_ADDRESS: u64 = 0x1000_0000;
+const ELF_HEADER_LEN: usize = 64;
+const PROGRAM_HEADER_LEN: usize = 56;
+const HEADERS_LEN: usize = ELF_HEADER_LEN + 2 * PROGRAM_HEADER_LEN;
+const PAGE_LEN: u64 = 0x1000;
+/// Bytes of output collected before they go out in a single `write`
+pub(super) const OUTPUT_BUFFER_LEN: u64 = 4096;
+
+pub(super) const OUT_OF_BOUNDS_MESSAGE: &[u8] = b"data pointer moved off the tape\n";
+pub(super) const EOF_MESSAGE: &[u8] = b"unexpected end of input\n";
+
+const SYS_READ: u64 = 0;
+const SYS_WRITE: u64 = 1;
+const SYS_EXIT_GROUP: u64 = 231;
+const EINTR: i32 = -4;
+
+const TAPE_START: Reg = Reg::Rbx;
+const DATA_PTR: Reg = Reg::R12;
+const TAPE_END: Reg = Reg::R13;
+/// the next free byte of the output buffer
+const OUTPUT: Reg = Reg::R15;
+
+/// Translates bytecode into a static Linux x86-64 executable that talks to the kernel through
+/// raw `read`, `write` and `exit_group` syscalls, so it needs nothing else to build or run.
+///
+/// The tape is a fixed block of `C` cells changed with plain `add` and `sub`, so they wrap, and
+/// output is buffered until the program reads, exits or fails. Leaving the tape or running into
+/// the end of input under [`EofPolicy::Error`] prints a message to stderr and exits with status 1
+pub struct ElfBackend<C: Cell = u8> {
+    tape_len: usize,
+    eof_policy: EofPolicy,
+    cell: PhantomData<C>,
+}
+
+impl<C: Cell> Default for ElfBackend<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Cell> ElfBackend<C> {
+    pub fn new() -> Self {
+        Self {
+            tape_len: DEFAULT_TAPE_LEN,
+            eof_policy: EofPolicy::default(),
+            cell: PhantomData,
+        }
+    }
+
+    pub fn with_tape_len(mut self, tape_len: usize) -> Self {
+        self.tape_len = tape_len;
+        self
+    }
+
+    /// what `,` does once `read` returns nothing. [`EofPolicy::Error`] flushes the output, writes
+    /// a message to stderr and exits with status 1
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    /// the whole executable for `code`
+    pub fn executable(&self, code: &[ByteCode]) -> io::Result<Vec<u8>> {
+        let layout = Layout::new::<C>(self.tape_len)?;
+        let machine_code = CodeGen::<C>::new(Assembler::new(), code, layout, self.eof_policy)
+            .assemble()
+            .finish();
+
+        let entry = HEADERS_LEN + OUT_OF_BOUNDS_MESSAGE.len() + EOF_MESSAGE.len();
+        let file_len = (entry + machine_code.len()) as u64;
+        let bss_len = layout.scratch + 1 - BSS_ADDRESS;
+        let mut elf = Vec::with_capacity(file_len as usize);
+        elf.extend_from_slice(b"\x7fELF");
+        //64 bit, little endian, version 1, System V ABI, padding
+        elf.extend_from_slice(&[2, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        elf.extend_from_slice(&2u16.to_le_bytes()); //executable
+        elf.extend_from_slice(&0x3eu16.to_le_bytes()); //x86-64
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&(TEXT_ADDRESS + entry as u64).to_le_bytes());
+        elf.extend_from_slice(&(ELF_HEADER_LEN as u64).to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes()); //no section headers
+        elf.extend_from_slice(&0u32.to_le_bytes());
+        elf.extend_from_slice(&(ELF_HEADER_LEN as u16).to_le_bytes());
+        elf.extend_from_slice(&(PROGRAM_HEADER_LEN as u16).to_le_bytes());
+        elf.extend_from_slice(&2u16.to_le_bytes());
+        elf.extend_from_slice(&[0; 6]);
+        //the file itself as read-only code, then the zeroed tape and buffers
+        Self::program_header(&mut elf, 5, TEXT_ADDRESS, file_len, file_len);
+        Self::program_header(&mut elf, 6, BSS_ADDRESS, 0, bss_len);
+        debug_assert_eq!(elf.len(), HEADERS_LEN);
+        elf.extend_from_slice(OUT_OF_BOUNDS_MESSAGE);
+        elf.extend_from_slice(EOF_MESSAGE);
+        elf.extend_from_slice(&machine_code);
+        Ok(elf)
+    }
+
+    /// a `PT_LOAD` segment mapping the start of the file, `flags` being the usual `rwx` bits
+    fn program_header(elf: &mut Vec<u8>, flags: u32, address: u64, file_len: u64, mem_len: u64) {
+        elf.extend_from_slice(&1u32.to_le_bytes());
+        elf.extend_from_slice(&flags.to_le_bytes());
+        elf.extend_from_slice(&0u64.to_le_bytes());
+        elf.extend_from_slice(&address.to_le_bytes());
+        elf.extend_from_slice(&address.to_le_bytes());
+        elf.extend_from_slice(&file_len.to_le_bytes());
+        elf.extend_from_slice(&mem_len.to_le_bytes());
+        elf.extend_from_slice(&PAGE_LEN.to_le_bytes());
+    }
+}
+
+impl<C: Cell> Backend for ElfBackend<C> {
+    fn emit(&self, code: &[ByteCode], output: &mut dyn Write) -> io::Result<()> {
+        output.write_all(&self.executable(code)?)
+    }
+}
+
+/// Addresses the code refers to, all of them below 2^31 so they fit in an immediate
+#[derive(Copy, Clone)]
+pub(super) struct Layout {
+    pub tape_bytes: u64,
+    tape_end: u64,
+    output_buffer: u64,
+    /// a byte for `read` to land in before it's widened into a cell
+    scratch: u64,
+    out_of_bounds_message: u64,
+    eof_message: u64,
+}
+
+impl Layout {
+    /// where everything goes for a tape of `tape_len` cells
+    pub fn new<C: Cell>(tape_len: usize) -> io::Result<Self> {
+        let tape_bytes = (tape_len as u64)
+            .checked_mul(C::BITS as u64 / 8)
+            .filter(|&bytes| bytes > 0 && BSS_ADDRESS + bytes + OUTPUT_BUFFER_LEN + 1 < 1 << 31)
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("a tape of {} cells doesn't fit an executable", tape_len),
+                )
+            })?;
+        Ok(Self {
+            tape_bytes,
+            tape_end: BSS_ADDRESS + tape_bytes,
+            output_buffer: BSS_ADDRESS + tape_bytes,
+            scratch: BSS_ADDRESS + tape_bytes + OUTPUT_BUFFER_LEN,
+            out_of_bounds_message: TEXT_ADDRESS + HEADERS_LEN as u64,
+            eof_message: TEXT_ADDRESS + (HEADERS_LEN + OUT_OF_BOUNDS_MESSAGE.len()) as u64,
+        })
+    }
+}
+
+/// Generates the code for a program, referring to the tape, buffers and messages by the names
+/// the assembly backend gives them
+pub(super) struct CodeGen<'a, C: Cell> {
+    asm: Assembler,
+    code: &'a [ByteCode],
+    layout: Layout,
+    eof_policy: EofPolicy,
+    width: Width,
+    /// the start of each instruction's code, plus one for the end of the program
+    labels: Vec<Label>,
+    flush: Label,
+    put: Label,
+    get: Label,
+    out_of_bounds: Label,
+    unexpected_eof: Label,
+    cell: PhantomData<C>,
+}
+
+impl<'a, C: Cell> CodeGen<'a, C> {
+    pub fn new(
+        mut asm: Assembler,
+        code: &'a [ByteCode],
+        layout: Layout,
+        eof_policy: EofPolicy,
+    ) -> Self {
+        let labels = (0..=code.len()).map(|_| asm.new_label()).collect();
+        Self {
+            code,
+            layout,
+            eof_policy,
+            width: Width::of_bits(C::BITS),
+            labels,
+            flush: asm.new_named_label("flush"),
+            put: asm.new_named_label("put"),
+            get: asm.new_named_label("get"),
+            out_of_bounds: asm.new_named_label("out_of_bounds"),
+            unexpected_eof: asm.new_named_label("unexpected_eof"),
+            asm,
+            cell: PhantomData,
+        }
+    }
+
+    /// generates the whole program, starting with its entry point
+    pub fn assemble(mut self) -> Assembler {
+        self.asm.mov_symbol(TAPE_START, "tape", BSS_ADDRESS);
+        self.asm
+            .mov_symbol(TAPE_END, "tape_end", self.layout.tape_end);
+        self.asm.mov(DATA_PTR, TAPE_START);
+        self.asm
+            .mov_symbol(OUTPUT, "output_buffer", self.layout.output_buffer);
+        for ip in 0..self.code.len() {
+            self.asm.mark(ip);
+            self.asm.bind(self.labels[ip]);
+            self.instruction(ip);
+        }
+        self.asm.bind(self.labels[self.code.len()]);
+        self.asm.call_label(self.flush);
+        self.exit(0);
+
+        self.flush_routine();
+        self.put_routine();
+        self.get_routine();
+        self.asm.bind(self.out_of_bounds);
+        self.fail(
+            "out_of_bounds_message",
+            self.layout.out_of_bounds_message,
+            OUT_OF_BOUNDS_MESSAGE.len(),
+        );
+        self.asm.bind(self.unexpected_eof);
+        self.fail("eof_message", self.layout.eof_message, EOF_MESSAGE.len());
+        self.asm
+    }
+
+    fn exit(&mut self, status: u64) {
+        self.asm.mov_imm(Reg::Rdi, status);
+        self.asm.mov_imm(Reg::Rax, SYS_EXIT_GROUP);
+        self.asm.syscall();
+    }
+
+    /// flushes the output, prints a message to stderr and exits with status 1
+    fn fail(&mut self, message: &'static str, address: u64, len: usize) {
+        self.asm.call_label(self.flush);
+        self.asm.mov_imm(Reg::Rax, SYS_WRITE);
+        self.asm.mov_imm(Reg::Rdi, 2);
+        self.asm.mov_symbol(Reg::Rsi, message, address);
+        self.asm.mov_imm(Reg::Rdx, len as u64);
+        self.asm.syscall();
+        self.exit(1);
+    }
+
+    /// writes out the output buffer, exiting with status 1 if stdout is gone
+    fn flush_routine(&mut self) {
+        let (write, done, failed) = (
+            self.asm.new_label(),
+            self.asm.new_label(),
+            self.asm.new_label(),
+        );
+        self.asm.bind(self.flush);
+        self.asm
+            .mov_symbol(Reg::Rsi, "output_buffer", self.layout.output_buffer);
+        self.asm.mov(Reg::Rdx, OUTPUT);
+        self.asm.sub(Reg::Rdx, Reg::Rsi);
+        self.asm.bind(write);
+        self.asm.test(Reg::Rdx);
+        self.asm.jcc(Cond::Equal, done);
+        self.asm.mov_imm(Reg::Rax, SYS_WRITE);
+        self.asm.mov_imm(Reg::Rdi, 1);
+        self.asm.syscall();
+        self.asm.cmp_imm(Reg::Rax, EINTR);
+        self.asm.jcc(Cond::Equal, write);
+        self.asm.test(Reg::Rax);
+        self.asm.jcc(Cond::LessOrEqual, failed);
+        self.asm.add(Reg::Rsi, Reg::Rax);
+        self.asm.sub(Reg::Rdx, Reg::Rax);
+        self.asm.jmp(write);
+        self.asm.bind(done);
+        self.asm
+            .mov_symbol(OUTPUT, "output_buffer", self.layout.output_buffer);
+        self.asm.ret();
+        self.asm.bind(failed);
+        self.exit(1);
+    }
+
+    /// appends `al` to the output buffer, flushing it once it's full
+    fn put_routine(&mut self) {
+        self.asm.bind(self.put);
+        self.asm
+            .store_cell(Width::Byte, Mem::at(OUTPUT, 0), Reg::Rax);
+        self.asm.add_imm(OUTPUT, 1);
+        let buffer_end = self.layout.output_buffer + OUTPUT_BUFFER_LEN;
+        self.asm.cmp_symbol(OUTPUT, "output_end", buffer_end);
+        self.asm.jcc(Cond::AboveOrEqual, self.flush);
+        self.asm.ret();
+    }
+
+    /// flushes the output and reads a byte into `rax`, which is negative at the end of input
+    fn get_routine(&mut self) {
+        let (read, eof) = (self.asm.new_label(), self.asm.new_label());
+        self.asm.bind(self.get);
+        self.asm.call_label(self.flush);
+        self.asm.bind(read);
+        self.asm.mov_imm(Reg::Rax, SYS_READ);
+        self.asm.mov_imm(Reg::Rdi, 0);
+        self.asm
+            .mov_symbol(Reg::Rsi, "scratch", self.layout.scratch);
+        self.asm.mov_imm(Reg::Rdx, 1);
+        self.asm.syscall();
+        self.asm.cmp_imm(Reg::Rax, EINTR);
+        self.asm.jcc(Cond::Equal, read);
+        self.asm.test(Reg::Rax);
+        self.asm.jcc(Cond::LessOrEqual, eof);
+        self.asm
+            .load_cell(Width::Byte, Reg::Rax, Mem::at(Reg::Rsi, 0));
+        self.asm.ret();
+        self.asm.bind(eof);
+        self.asm.mov_imm(Reg::Rax, u64::MAX);
+        self.asm.ret();
+    }
+
+    fn truncate(&self, value: isize) -> u64 {
+        self.width.truncate(value as i128)
+    }
+
+    /// puts the address of the cell `offset` away in `rdx`, failing if it's off the tape
+    fn checked_address(&mut self, offset: isize) -> Mem {
+        if offset == 0 {
+            return Mem::at(DATA_PTR, 0);
+        }
+        let disp = (offset as i64)
+            .checked_mul(self.width.bytes())
+            .and_then(|disp| i32::try_from(disp).ok());
+        let Some(disp) = disp else {
+            //too far to be on any tape that fits an executable
+            self.asm.jmp(self.out_of_bounds);
+            return Mem::at(DATA_PTR, 0);
+        };
+        self.asm.lea(Reg::Rdx, Mem::at(DATA_PTR, disp));
+        self.asm.cmp(Reg::Rdx, TAPE_START);
+        self.asm.jcc(Cond::Below, self.out_of_bounds);
+        self.asm.cmp(Reg::Rdx, TAPE_END);
+        self.asm.jcc(Cond::AboveOrEqual, self.out_of_bounds);
+        Mem::at(Reg::Rdx, 0)
+    }
+
+    fn move_data_ptr(&mut self, amount: isize) {
+        let mem = self.checked_address(amount);
+        if amount != 0 {
+            self.asm.mov(DATA_PTR, mem.base);
+        }
+    }
+
+    fn instruction(&mut self, ip: usize) {
+        let width = self.width;
+        let current = Mem::at(DATA_PTR, 0);
+        match self.code[ip] {
+            ByteCode::NOP => {}
+            ByteCode::IncrementDataPtr => self.move_data_ptr(1),
+            ByteCode::DecrementDataPtr => self.move_data_ptr(-1),
+            ByteCode::MoveDataPtr(amount) => self.move_data_ptr(amount),
+            ByteCode::IncrementByte => self.asm.add_cell(width, current, 1),
+            ByteCode::DecrementByte => {
+                let value = self.truncate(-1);
+                self.asm.add_cell(width, current, value);
+            }
+            ByteCode::AddByte(delta) => {
+                let value = self.truncate(delta);
+                self.asm.add_cell(width, current, value);
+            }
+            ByteCode::AddByteAt { offset, delta } => {
+                let mem = self.checked_address(offset);
+                let value = self.truncate(delta);
+                self.asm.add_cell(width, mem, value);
+            }
+            ByteCode::SetByte(value) => {
+                let value = self.truncate(value);
+                self.asm.set_cell(width, current, value);
+            }
+            ByteCode::SetByteAt { offset, value } => {
+                let mem = self.checked_address(offset);
+                let value = self.truncate(value);
+                self.asm.set_cell(width, mem, value);
+            }
+            ByteCode::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => {
+                let skip = self.asm.new_label();
+                let source = self.checked_address(source);
+                self.asm.load_cell(width, Reg::Rax, source);
+                self.asm.test(Reg::Rax);
+                self.asm.jcc(Cond::Equal, skip);
+                let target = self.checked_address(offset);
+                let factor = self.truncate(factor);
+                self.asm.mov_imm(Reg::Rcx, factor);
+                self.asm.imul(Reg::Rax, Reg::Rcx);
+                self.asm.add_cell_reg(width, target, Reg::Rax);
+                self.asm.bind(skip);
+            }
+            ByteCode::Scan(stride) => {
+                let (top, done) = (self.asm.new_label(), self.asm.new_label());
+                self.asm.bind(top);
+                self.asm.cmp_cell_zero(width, current);
+                self.asm.jcc(Cond::Equal, done);
+                self.move_data_ptr(stride);
+                self.asm.jmp(top);
+                self.asm.bind(done);
+            }
+            ByteCode::OutputByte => self.output(0),
+            ByteCode::OutputByteAt(offset) => self.output(offset),
+            ByteCode::OutputConst(byte) => {
+                self.asm.mov_imm(Reg::Rax, byte as u64);
+                self.asm.call_label(self.put);
+            }
+            ByteCode::InputByte => self.input(0),
+            ByteCode::InputByteAt(offset) => self.input(offset),
+            ByteCode::LoopOpen { close_location } => {
+                self.asm.cmp_cell_zero(width, current);
+                self.asm.jcc(Cond::Equal, self.labels[close_location + 1]);
+            }
+            ByteCode::LoopClose { open_location } => {
+                self.asm.cmp_cell_zero(width, current);
+                self.asm.jcc(Cond::NotEqual, self.labels[open_location]);
+            }
+        }
+    }
+
+    fn output(&mut self, offset: isize) {
+        let mem = self.checked_address(offset);
+        self.asm.load_cell(self.width, Reg::Rax, mem);
+        self.asm.call_label(self.put);
+    }
+
+    fn input(&mut self, offset: isize) {
+        let (eof, done) = (self.asm.new_label(), self.asm.new_label());
+        self.asm.call_label(self.get);
+        let mem = self.checked_address(offset);
+        self.asm.test(Reg::Rax);
+        self.asm.jcc(Cond::Sign, eof);
+        self.asm.store_cell(self.width, mem, Reg::Rax);
+        self.asm.jmp(done);
+        self.asm.bind(eof);
+        match self.eof_policy {
+            EofPolicy::Zero => self.asm.set_cell(self.width, mem, 0),
+            EofPolicy::Max => {
+                let max = self.truncate(-1);
+                self.asm.set_cell(self.width, mem, max);
+            }
+            EofPolicy::Unchanged => {}
+            EofPolicy::Error => self.asm.jmp(self.unexpected_eof),
+        }
+        self.asm.bind(done);
+    }
+}
+
+#[test]
+fn emits_an_elf_executable() {
+    let code = Compiler::compile("+[.+]").unwrap();
+    let elf = ElfBackend::<u16>::new()
+        .with_tape_len(100)
+        .executable(&code)
+        .unwrap();
+    assert_eq!(&elf[..4], b"\x7fELF");
+    let entry = u64::from_le_bytes(elf[24..32].try_into().unwrap());
+    assert!(entry > TEXT_ADDRESS && entry < TEXT_ADDRESS + elf.len() as u64);
+    //the tape segment covers 100 two byte cells and the output buffer
+    let bss_len = u64::from_le_bytes(elf[160..168].try_into().unwrap());
+    assert_eq!(bss_len, 200 + OUTPUT_BUFFER_LEN + 1);
+
+    let too_big = ElfBackend::<u64>::new().with_tape_len(usize::MAX / 4);
+    assert!(too_big.executable(&code).is_err());
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let hello_world = "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
+        let cases = [
+            (hello_world, EofPolicy::Zero, &b""[..], 0),
+            (",[->+++<]>.,.", EofPolicy::Zero, b"\x05", 0),
+            (",.,.", EofPolicy::Zero, b"a", 0),
+            (",.,.", EofPolicy::Max, b"a", 0),
+            (",.,.", EofPolicy::Unchanged, b"a", 0),
+            //both print what they have so far and exit with status 1
+            (",.,.", EofPolicy::Error, b"a", 1),
+            ("+.<.", EofPolicy::Zero, b"", 1),
+        ];
+        let dir =
+            std::env::temp_dir().join(format!("brainfrusk-elf-backend-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (i, (source, eof_policy, input, status)) in cases.into_iter().enumerate() {
+            let code = Compiler::compile(source).unwrap();
+            let elf = ElfBackend::<u8>::new()
+                .with_tape_len(100)
+                .with_eof_policy(eof_policy)
+                .executable(&code)
+                .unwrap();
+            let path = dir.join(format!("program{}", i));
+            std::fs::write(&path, elf).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            let run = run_program(&path, input);
+            assert_eq!(run.0, Some(status), "{} with {:?}", source, eof_policy);
+            assert_eq!(
+                run,
+                run_interpreter::<u8>(&code, 100, eof_policy, input),
+                "{} with {:?}",
+                source,
+                eof_policy
+            );
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,294 @@
+use super::*;
+use std::marker::PhantomData;
+
+/// Translates bytecode into Rust source that runs the program without an interpreter.
+///
+/// By default the output is a module holding
+/// `pub fn run<R: Read, W: Write>(input: &mut R, output: &mut W) -> io::Result<()>`, meant to be
+/// written to `OUT_DIR` by a build script and pulled in with `include!`.
+/// [`RustBackend::with_main`] adds a `main` that runs it on stdin and stdout instead.
+///
+/// Cells change through `wrapping_add` and friends, so they never panic on overflow, but a
+/// pointer that leaves the tape panics on the next access to it
+pub struct RustBackend<C: Cell = u8> {
+    tape_len: usize,
+    eof_policy: EofPolicy,
+    function_name: String,
+    main: bool,
+    cell: PhantomData<C>,
+}
+
+impl<C: Cell> Default for RustBackend<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Cell> RustBackend<C> {
+    pub fn new() -> Self {
+        Self {
+            tape_len: DEFAULT_TAPE_LEN,
+            eof_policy: EofPolicy::default(),
+            function_name: String::from("run"),
+            main: false,
+            cell: PhantomData,
+        }
+    }
+
+    pub fn with_tape_len(mut self, tape_len: usize) -> Self {
+        self.tape_len = tape_len;
+        self
+    }
+
+    /// what `,` does at the end of input, [`EofPolicy::Error`] makes the function return an
+    /// [`io::ErrorKind::UnexpectedEof`] error
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    /// renames the generated function, so several programs can live in the same module
+    pub fn with_function_name<S: Into<String>>(mut self, function_name: S) -> Self {
+        self.function_name = function_name.into();
+        self
+    }
+
+    /// also emits a `main` so the output builds as a binary on its own
+    pub fn with_main(mut self) -> Self {
+        self.main = true;
+        self
+    }
+
+    /// `value` as a literal of the cell type, wrapped like the interpreter would
+    fn literal(value: isize) -> i128 {
+        C::from_i128_wrapping(value as i128).to_i128()
+    }
+
+    fn cell_at(offset: isize) -> String {
+        match offset {
+            0 => String::from("tape[p]"),
+            _ => format!("tape[p.wrapping_add_signed({})]", offset),
+        }
+    }
+
+    fn emit_prelude(&self, output: &mut dyn Write) -> io::Result<()> {
+        let on_eof = match self.eof_policy {
+            EofPolicy::Zero => "*target = 0;",
+            EofPolicy::Max => "*target = Cell::MAX;",
+            EofPolicy::Unchanged => "",
+            EofPolicy::Error => {
+                "return Err(io::Error::new(io::ErrorKind::UnexpectedEof, \"unexpected end of input\"));"
+            }
+        };
+        writeln!(output, "// generated by brainfrusk, do not edit")?;
+        writeln!(output)?;
+        writeln!(
+            output,
+            "#[allow(unused_mut, unused_variables, unused_assignments, clippy::all)]"
+        )?;
+        writeln!(
+            output,
+            "pub fn {}<R: std::io::Read, W: std::io::Write>(input: &mut R, output: &mut W) -> std::io::Result<()> {{",
+            self.function_name
+        )?;
+        writeln!(output, "    use std::io;")?;
+        writeln!(output, "    type Cell = u{};", C::BITS)?;
+        writeln!(output)?;
+        writeln!(output, "    #[allow(dead_code)]")?;
+        writeln!(
+            output,
+            "    fn read<R: io::Read>(input: &mut R, target: &mut Cell) -> io::Result<()> {{"
+        )?;
+        writeln!(output, "        let mut byte = [0];")?;
+        writeln!(output, "        match input.read(&mut byte) {{")?;
+        writeln!(output, "            Ok(0) => {{")?;
+        if !on_eof.is_empty() {
+            writeln!(output, "                {}", on_eof)?;
+        }
+        writeln!(output, "            }}")?;
+        writeln!(output, "            Ok(_) => *target = byte[0] as Cell,")?;
+        writeln!(
+            output,
+            "            Err(err) if err.kind() == io::ErrorKind::Interrupted => return read(input, target),"
+        )?;
+        writeln!(output, "            Err(err) => return Err(err),")?;
+        writeln!(output, "        }}")?;
+        writeln!(output, "        Ok(())")?;
+        writeln!(output, "    }}")?;
+        writeln!(output)?;
+        writeln!(
+            output,
+            "    let mut tape: Vec<Cell> = vec![0; {}];",
+            self.tape_len
+        )?;
+        writeln!(output, "    let mut p: usize = 0;")?;
+        Ok(())
+    }
+
+    /// the Rust statement for a single instruction, `None` for the ones that open or close a
+    /// block and for [`ByteCode::OutputConst`], which gets batched
+    fn statement(inst: ByteCode) -> Option<String> {
+        let statement = match inst {
+            ByteCode::NOP => String::new(),
+            ByteCode::IncrementDataPtr => String::from("p = p.wrapping_add(1);"),
+            ByteCode::DecrementDataPtr => String::from("p = p.wrapping_sub(1);"),
+            ByteCode::MoveDataPtr(amount) => format!("p = p.wrapping_add_signed({});", amount),
+            ByteCode::IncrementByte => String::from("tape[p] = tape[p].wrapping_add(1);"),
+            ByteCode::DecrementByte => String::from("tape[p] = tape[p].wrapping_sub(1);"),
+            ByteCode::AddByte(delta) => Self::statement(ByteCode::AddByteAt { offset: 0, delta })?,
+            ByteCode::AddByteAt { offset, delta } => {
+                let cell = Self::cell_at(offset);
+                format!("{0} = {0}.wrapping_add({1});", cell, Self::literal(delta))
+            }
+            ByteCode::SetByte(value) => Self::statement(ByteCode::SetByteAt { offset: 0, value })?,
+            ByteCode::SetByteAt { offset, value } => {
+                format!("{} = {};", Self::cell_at(offset), Self::literal(value))
+            }
+            ByteCode::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => {
+                //the target may be off the tape when the source is zero, like in the interpreter
+                let (source, cell) = (Self::cell_at(source), Self::cell_at(offset));
+                format!(
+                    "if {0} != 0 {{ {1} = {1}.wrapping_add({0}.wrapping_mul({2})); }}",
+                    source,
+                    cell,
+                    Self::literal(factor)
+                )
+            }
+            ByteCode::Scan(stride) => format!(
+                "while tape[p] != 0 {{ p = p.wrapping_add_signed({}); }}",
+                stride
+            ),
+            ByteCode::OutputByte => String::from("output.write_all(&[tape[p] as u8])?;"),
+            ByteCode::OutputByteAt(offset) => {
+                format!("output.write_all(&[{} as u8])?;", Self::cell_at(offset))
+            }
+            ByteCode::InputByte => String::from("output.flush()?;\nread(input, &mut tape[p])?;"),
+            ByteCode::InputByteAt(offset) => format!(
+                "output.flush()?;\nread(input, &mut {})?;",
+                Self::cell_at(offset)
+            ),
+            ByteCode::OutputConst(_) | ByteCode::LoopOpen { .. } | ByteCode::LoopClose { .. } => {
+                return None
+            }
+        };
+        Some(statement)
+    }
+}
+
+impl<C: Cell> Backend for RustBackend<C> {
+    fn emit(&self, code: &[ByteCode], output: &mut dyn Write) -> io::Result<()> {
+        self.emit_prelude(output)?;
+        let mut depth = 1;
+        let mut idx = 0;
+        while idx < code.len() {
+            let indent = depth * 4;
+            match code[idx] {
+                ByteCode::LoopOpen { .. } => {
+                    writeln!(output, "{:indent$}while tape[p] != 0 {{", "")?;
+                    depth += 1;
+                }
+                ByteCode::LoopClose { .. } => {
+                    depth -= 1;
+                    writeln!(output, "{:indent$}}}", "", indent = depth * 4)?;
+                }
+                ByteCode::OutputConst(_) => {
+                    //runs of constant output, like a folded prelude, go out in one write
+                    let mut bytes = Vec::new();
+                    while let Some(&ByteCode::OutputConst(byte)) = code.get(idx) {
+                        bytes.push(byte);
+                        idx += 1;
+                    }
+                    let literal = bytes.escape_ascii().to_string();
+                    writeln!(output, "{:indent$}output.write_all(b\"{}\")?;", "", literal)?;
+                    continue;
+                }
+                inst => {
+                    for line in Self::statement(inst).iter().flat_map(|s| s.lines()) {
+                        writeln!(output, "{:indent$}{}", "", line)?;
+                    }
+                }
+            }
+            idx += 1;
+        }
+        writeln!(output, "    output.flush()")?;
+        writeln!(output, "}}")?;
+
+        if self.main {
+            writeln!(output)?;
+            writeln!(output, "fn main() -> std::io::Result<()> {{")?;
+            writeln!(output, "    let mut input = std::io::stdin().lock();")?;
+            writeln!(
+                output,
+                "    let mut output = std::io::BufWriter::new(std::io::stdout().lock());"
+            )?;
+            writeln!(
+                output,
+                "    {}(&mut input, &mut output)",
+                self.function_name
+            )?;
+            writeln!(output, "}}")?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn emits_a_rust_module() {
+    let code = Compiler::compile_for_output("++++++++[>++++++++<-]>+.,[-<+>]").unwrap();
+    let module = RustBackend::<u16>::new()
+        .with_function_name("shout")
+        .with_eof_policy(EofPolicy::Error)
+        .emit_to_string(&code);
+    assert!(module.contains("pub fn shout<R: std::io::Read, W: std::io::Write>"));
+    assert!(module.contains("type Cell = u16;"));
+    assert!(module.contains("output.write_all(b\"A\")?;"));
+    assert!(module.contains("UnexpectedEof"));
+    assert!(!module.contains("fn main"));
+    assert_eq!(module.matches('{').count(), module.matches('}').count());
+
+    let code = Compiler::compile(">,[-<++>]<[.>]").unwrap();
+    let binary = RustBackend::<u8>::new().with_main().emit_to_string(&code);
+    assert!(binary.contains("fn main() -> std::io::Result<()> {"));
+    assert!(binary.contains(
+        "tape[p] = tape[p].wrapping_add(tape[p.wrapping_add_signed(1)].wrapping_mul(2));"
+    ));
+    assert!(binary.contains("while tape[p] != 0 {"));
+}
+
+#[test]
+fn emitted_program_builds_and_runs() {
+    //folds into a constant `"\` followed by a program that echoes its input
+    let source = format!("{}.{}.,[.,]", "+".repeat(34), "+".repeat(58));
+    let code = Compiler::compile_for_output(&source).unwrap();
+    let program = RustBackend::<u8>::new().with_main().emit_to_string(&code);
+    assert!(program.contains(r#"output.write_all(b"\"\\")?;"#));
+
+    let dir = std::env::temp_dir().join(format!("brainfrusk-rust-backend-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let (path, binary) = (dir.join("main.rs"), dir.join("main"));
+    std::fs::write(&path, program).unwrap();
+    let rustc = std::process::Command::new(option_env!("RUSTC").unwrap_or("rustc"))
+        .arg(&path)
+        .arg("-o")
+        .arg(&binary)
+        .output()
+        .unwrap();
+    assert!(
+        rustc.status.success(),
+        "{}",
+        String::from_utf8_lossy(&rustc.stderr)
+    );
+    let mut child = std::process::Command::new(&binary)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"ok").unwrap();
+    let run = child.wait_with_output().unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(run.stdout, b"\"\\ok");
+}
@@ -0,0 +1,687 @@
+//! Just enough of an x86-64 assembler for the JIT and the native backends. Only encodes the
+//! handful of instructions they need, jumps always take a 32 bit displacement so labels can be
+//! bound after the jumps to them are emitted. It can also keep a [`Line`] listing of what it
+//! encoded for the assembly backend to print
+
+/// The general purpose registers, in encoding order
+#[allow(dead_code)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Reg {
+    Rax,
+    Rcx,
+    Rdx,
+    Rbx,
+    Rsp,
+    Rbp,
+    Rsi,
+    Rdi,
+    R8,
+    R9,
+    R10,
+    R11,
+    R12,
+    R13,
+    R14,
+    R15,
+}
+
+impl Reg {
+    fn low(self) -> u8 {
+        self as u8 & 7
+    }
+
+    fn is_extended(self) -> bool {
+        self as u8 >= 8
+    }
+
+    /// the Intel name of the low `width` bits of the register
+    pub fn name(self, width: Width) -> &'static str {
+        const NAMES: [[&str; 4]; 16] = [
+            ["al", "ax", "eax", "rax"],
+            ["cl", "cx", "ecx", "rcx"],
+            ["dl", "dx", "edx", "rdx"],
+            ["bl", "bx", "ebx", "rbx"],
+            ["spl", "sp", "esp", "rsp"],
+            ["bpl", "bp", "ebp", "rbp"],
+            ["sil", "si", "esi", "rsi"],
+            ["dil", "di", "edi", "rdi"],
+            ["r8b", "r8w", "r8d", "r8"],
+            ["r9b", "r9w", "r9d", "r9"],
+            ["r10b", "r10w", "r10d", "r10"],
+            ["r11b", "r11w", "r11d", "r11"],
+            ["r12b", "r12w", "r12d", "r12"],
+            ["r13b", "r13w", "r13d", "r13"],
+            ["r14b", "r14w", "r14d", "r14"],
+            ["r15b", "r15w", "r15d", "r15"],
+        ];
+        NAMES[self as usize][width as usize]
+    }
+}
+
+/// Operand size of the instructions that work on tape cells
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Width {
+    Byte,
+    Word,
+    Dword,
+    Qword,
+}
+
+impl Width {
+    pub fn of_bits(bits: u32) -> Self {
+        match bits {
+            8 => Self::Byte,
+            16 => Self::Word,
+            32 => Self::Dword,
+            _ => Self::Qword,
+        }
+    }
+
+    pub fn bytes(self) -> i64 {
+        match self {
+            Self::Byte => 1,
+            Self::Word => 2,
+            Self::Dword => 4,
+            Self::Qword => 8,
+        }
+    }
+
+    /// `value` truncated to the width, the way a store of it would
+    pub fn truncate(self, value: i128) -> u64 {
+        let value = value as u64;
+        match self {
+            Self::Qword => value,
+            _ => value & ((1 << (self.bytes() * 8)) - 1),
+        }
+    }
+
+    /// `value`, already truncated to the width, read back as a signed number
+    pub fn sign_extend(self, value: u64) -> i64 {
+        let unused = 64 - self.bytes() * 8;
+        ((value << unused) as i64) >> unused
+    }
+}
+
+/// Condition codes for [`Assembler::jcc`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Cond {
+    /// unsigned less than
+    Below = 0x2,
+    /// unsigned greater or equal
+    AboveOrEqual = 0x3,
+    Equal = 0x4,
+    NotEqual = 0x5,
+    /// negative
+    Sign = 0x8,
+    /// signed less or equal
+    LessOrEqual = 0xe,
+}
+
+impl Cond {
+    /// the jump's mnemonic
+    pub fn jump(self) -> &'static str {
+        match self {
+            Self::Below => "jb",
+            Self::AboveOrEqual => "jae",
+            Self::Equal => "je",
+            Self::NotEqual => "jne",
+            Self::Sign => "js",
+            Self::LessOrEqual => "jle",
+        }
+    }
+}
+
+/// A memory operand, `[base + disp]`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Mem {
+    pub base: Reg,
+    pub disp: i32,
+}
+
+impl Mem {
+    pub fn at(base: Reg, disp: i32) -> Self {
+        Self { base, disp }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Operand {
+    Reg(Reg),
+    Mem(Mem),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct Label(usize);
+
+impl Label {
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+/// An operand of a listed instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Arg {
+    Reg(Reg, Width),
+    Mem(Mem, Width),
+    Imm(i64),
+    /// an address the program refers to by name
+    Symbol(&'static str),
+    Label(Label),
+}
+
+/// An instruction the way an assembler would spell it in Intel order, destination first
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Insn {
+    pub mnemonic: &'static str,
+    pub args: Vec<Arg>,
+}
+
+/// What an [`Assembler`] that keeps a listing saw, in order
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Line {
+    /// the code for bytecode instruction `n` starts here
+    Mark(usize),
+    Bind(Label),
+    Insn(Insn),
+}
+
+#[derive(Default)]
+pub(crate) struct Assembler {
+    code: Vec<u8>,
+    labels: Vec<Option<usize>>,
+    /// names to print labels by instead of their number
+    names: Vec<Option<&'static str>>,
+    /// where a jump's displacement sits along with the label it jumps to
+    fixups: Vec<(usize, Label)>,
+    listing: Option<Vec<Line>>,
+}
+
+impl Assembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// an assembler that also lists every instruction it encodes
+    pub fn with_listing() -> Self {
+        Self {
+            listing: Some(Vec::new()),
+            ..Self::default()
+        }
+    }
+
+    pub fn new_label(&mut self) -> Label {
+        self.labels.push(None);
+        self.names.push(None);
+        Label(self.labels.len() - 1)
+    }
+
+    /// a label the listing calls `name`
+    pub fn new_named_label(&mut self, name: &'static str) -> Label {
+        let label = self.new_label();
+        self.names[label.0] = Some(name);
+        label
+    }
+
+    pub fn name(&self, label: Label) -> Option<&'static str> {
+        self.names[label.0]
+    }
+
+    /// points `label` at the next instruction
+    pub fn bind(&mut self, label: Label) {
+        debug_assert!(self.labels[label.0].is_none(), "label bound twice");
+        self.labels[label.0] = Some(self.code.len());
+        self.list(Line::Bind(label));
+    }
+
+    /// notes in the listing that bytecode instruction `ip` starts here
+    pub fn mark(&mut self, ip: usize) {
+        self.list(Line::Mark(ip));
+    }
+
+    /// what has been encoded so far, if the assembler keeps a listing
+    pub fn listing(&self) -> Option<&[Line]> {
+        self.listing.as_deref()
+    }
+
+    fn list(&mut self, line: Line) {
+        if let Some(listing) = &mut self.listing {
+            listing.push(line);
+        }
+    }
+
+    fn note(&mut self, mnemonic: &'static str, args: &[Arg]) {
+        self.list(Line::Insn(Insn {
+            mnemonic,
+            args: args.to_vec(),
+        }));
+    }
+
+    /// where `label` was bound, once it has been
+    pub fn offset(&self, label: Label) -> Option<usize> {
+        self.labels[label.0]
+    }
+
+    /// resolves every jump and hands back the machine code
+    pub fn finish(mut self) -> Vec<u8> {
+        for &(at, label) in &self.fixups {
+            let target = self.labels[label.0].expect("jump to a label that was never bound");
+            let rel = target as i64 - (at as i64 + 4);
+            let rel = i32::try_from(rel).expect("jump out of range");
+            self.code[at..at + 4].copy_from_slice(&rel.to_le_bytes());
+        }
+        self.code
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.code.extend_from_slice(bytes);
+    }
+
+    /// emits an instruction with a ModRM byte: operand size prefix, REX, `opcode`, then `reg`
+    /// and `rm` encoded as the two operands
+    fn modrm(&mut self, width: Width, opcode: &[u8], reg: u8, rm: Operand) {
+        if width == Width::Word {
+            self.code.push(0x66);
+        }
+        let base = match rm {
+            Operand::Reg(reg) | Operand::Mem(Mem { base: reg, .. }) => reg,
+        };
+        let rex = 0x40
+            | (u8::from(width == Width::Qword) << 3)
+            | (u8::from(reg >= 8) << 2)
+            | u8::from(base.is_extended());
+        if rex != 0x40 {
+            self.code.push(rex);
+        }
+        self.bytes(opcode);
+        let reg = (reg & 7) << 3;
+        match rm {
+            Operand::Reg(rm) => self.code.push(0xc0 | reg | rm.low()),
+            Operand::Mem(Mem { base, disp }) => {
+                //rbp and r13 can't go without a displacement, rsp and r12 need a SIB byte
+                let mode = if disp == 0 && base.low() != 5 {
+                    0x00
+                } else if i8::try_from(disp).is_ok() {
+                    0x40
+                } else {
+                    0x80
+                };
+                self.code.push(mode | reg | base.low());
+                if base.low() == 4 {
+                    self.code.push(0x24);
+                }
+                match mode {
+                    0x40 => self.code.push(disp as u8),
+                    0x80 => self.bytes(&disp.to_le_bytes()),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    pub fn push(&mut self, reg: Reg) {
+        if reg.is_extended() {
+            self.code.push(0x41);
+        }
+        self.code.push(0x50 + reg.low());
+        self.note("push", &[Arg::Reg(reg, Width::Qword)]);
+    }
+
+    pub fn pop(&mut self, reg: Reg) {
+        if reg.is_extended() {
+            self.code.push(0x41);
+        }
+        self.code.push(0x58 + reg.low());
+        self.note("pop", &[Arg::Reg(reg, Width::Qword)]);
+    }
+
+    pub fn ret(&mut self) {
+        self.code.push(0xc3);
+        self.note("ret", &[]);
+    }
+
+    pub fn syscall(&mut self) {
+        self.bytes(&[0x0f, 0x05]);
+        self.note("syscall", &[]);
+    }
+
+    /// `mov dst, src`
+    pub fn mov(&mut self, dst: Reg, src: Reg) {
+        self.modrm(Width::Qword, &[0x89], src as u8, Operand::Reg(dst));
+        self.note(
+            "mov",
+            &[Arg::Reg(dst, Width::Qword), Arg::Reg(src, Width::Qword)],
+        );
+    }
+
+    /// `mov dst, imm`, picking the shortest encoding for the value
+    pub fn mov_imm(&mut self, dst: Reg, imm: u64) {
+        if let Ok(imm) = u32::try_from(imm) {
+            if dst.is_extended() {
+                self.code.push(0x41);
+            }
+            self.code.push(0xb8 + dst.low());
+            self.bytes(&imm.to_le_bytes());
+            self.note("mov", &[Arg::Reg(dst, Width::Dword), Arg::Imm(imm.into())]);
+        } else {
+            self.code.push(0x48 | u8::from(dst.is_extended()));
+            self.code.push(0xb8 + dst.low());
+            self.bytes(&imm.to_le_bytes());
+            self.note("mov", &[Arg::Reg(dst, Width::Qword), Arg::Imm(imm as i64)]);
+        }
+    }
+
+    /// `mov dst, symbol`, where `address` is what the symbol is at and fits 32 bits
+    pub fn mov_symbol(&mut self, dst: Reg, symbol: &'static str, address: u64) {
+        let address = u32::try_from(address).expect("symbols sit in the low 4GiB");
+        self.with_note(
+            |asm| asm.mov_imm(dst, address.into()),
+            "mov",
+            &[Arg::Reg(dst, Width::Dword), Arg::Symbol(symbol)],
+        );
+    }
+
+    /// encodes with `encode` but lists the instruction as `mnemonic args`
+    fn with_note(&mut self, encode: impl FnOnce(&mut Self), mnemonic: &'static str, args: &[Arg]) {
+        let listing = self.listing.take();
+        encode(self);
+        self.listing = listing;
+        self.note(mnemonic, args);
+    }
+
+    /// `mov dst, qword [mem]`
+    pub fn load(&mut self, dst: Reg, mem: Mem) {
+        self.modrm(Width::Qword, &[0x8b], dst as u8, Operand::Mem(mem));
+        self.note(
+            "mov",
+            &[Arg::Reg(dst, Width::Qword), Arg::Mem(mem, Width::Qword)],
+        );
+    }
+
+    /// `mov qword [mem], src`
+    pub fn store(&mut self, mem: Mem, src: Reg) {
+        self.modrm(Width::Qword, &[0x89], src as u8, Operand::Mem(mem));
+        self.note(
+            "mov",
+            &[Arg::Mem(mem, Width::Qword), Arg::Reg(src, Width::Qword)],
+        );
+    }
+
+    /// `lea dst, [mem]`
+    pub fn lea(&mut self, dst: Reg, mem: Mem) {
+        self.modrm(Width::Qword, &[0x8d], dst as u8, Operand::Mem(mem));
+        self.note(
+            "lea",
+            &[Arg::Reg(dst, Width::Qword), Arg::Mem(mem, Width::Qword)],
+        );
+    }
+
+    /// `cmp a, b`
+    pub fn cmp(&mut self, a: Reg, b: Reg) {
+        self.modrm(Width::Qword, &[0x39], b as u8, Operand::Reg(a));
+        self.note(
+            "cmp",
+            &[Arg::Reg(a, Width::Qword), Arg::Reg(b, Width::Qword)],
+        );
+    }
+
+    /// `cmp reg, imm`
+    pub fn cmp_imm(&mut self, reg: Reg, imm: i32) {
+        self.modrm(Width::Qword, &[0x81], 7, Operand::Reg(reg));
+        self.bytes(&imm.to_le_bytes());
+        self.note("cmp", &[Arg::Reg(reg, Width::Qword), Arg::Imm(imm.into())]);
+    }
+
+    /// `cmp reg, symbol`, see [`Assembler::mov_symbol`]
+    pub fn cmp_symbol(&mut self, reg: Reg, symbol: &'static str, address: u64) {
+        let address = i32::try_from(address).expect("symbols sit in the low 2GiB");
+        self.with_note(
+            |asm| asm.cmp_imm(reg, address),
+            "cmp",
+            &[Arg::Reg(reg, Width::Qword), Arg::Symbol(symbol)],
+        );
+    }
+
+    /// `add reg, imm`
+    pub fn add_imm(&mut self, reg: Reg, imm: i32) {
+        self.modrm(Width::Qword, &[0x81], 0, Operand::Reg(reg));
+        self.bytes(&imm.to_le_bytes());
+        self.note("add", &[Arg::Reg(reg, Width::Qword), Arg::Imm(imm.into())]);
+    }
+
+    /// `add dst, src`
+    pub fn add(&mut self, dst: Reg, src: Reg) {
+        self.modrm(Width::Qword, &[0x01], src as u8, Operand::Reg(dst));
+        self.note(
+            "add",
+            &[Arg::Reg(dst, Width::Qword), Arg::Reg(src, Width::Qword)],
+        );
+    }
+
+    /// `sub dst, src`
+    pub fn sub(&mut self, dst: Reg, src: Reg) {
+        self.modrm(Width::Qword, &[0x29], src as u8, Operand::Reg(dst));
+        self.note(
+            "sub",
+            &[Arg::Reg(dst, Width::Qword), Arg::Reg(src, Width::Qword)],
+        );
+    }
+
+    /// `sub reg, imm`
+    pub fn sub_imm(&mut self, reg: Reg, imm: i32) {
+        self.modrm(Width::Qword, &[0x81], 5, Operand::Reg(reg));
+        self.bytes(&imm.to_le_bytes());
+        self.note("sub", &[Arg::Reg(reg, Width::Qword), Arg::Imm(imm.into())]);
+    }
+
+    /// `imul dst, src`
+    pub fn imul(&mut self, dst: Reg, src: Reg) {
+        self.modrm(Width::Qword, &[0x0f, 0xaf], dst as u8, Operand::Reg(src));
+        self.note(
+            "imul",
+            &[Arg::Reg(dst, Width::Qword), Arg::Reg(src, Width::Qword)],
+        );
+    }
+
+    /// `test reg, reg`
+    pub fn test(&mut self, reg: Reg) {
+        self.modrm(Width::Qword, &[0x85], reg as u8, Operand::Reg(reg));
+        self.note(
+            "test",
+            &[Arg::Reg(reg, Width::Qword), Arg::Reg(reg, Width::Qword)],
+        );
+    }
+
+    /// `test al, al`, checks the `bool` a call returned
+    pub fn test_al(&mut self) {
+        self.bytes(&[0x84, 0xc0]);
+        self.note(
+            "test",
+            &[
+                Arg::Reg(Reg::Rax, Width::Byte),
+                Arg::Reg(Reg::Rax, Width::Byte),
+            ],
+        );
+    }
+
+    /// `call reg`
+    pub fn call(&mut self, reg: Reg) {
+        self.modrm(Width::Dword, &[0xff], 2, Operand::Reg(reg));
+        self.note("call", &[Arg::Reg(reg, Width::Qword)]);
+    }
+
+    /// `jmp reg`
+    pub fn jmp_reg(&mut self, reg: Reg) {
+        self.modrm(Width::Dword, &[0xff], 4, Operand::Reg(reg));
+        self.note("jmp", &[Arg::Reg(reg, Width::Qword)]);
+    }
+
+    /// `call label`
+    pub fn call_label(&mut self, label: Label) {
+        self.code.push(0xe8);
+        self.fixup(label);
+        self.note("call", &[Arg::Label(label)]);
+    }
+
+    pub fn jmp(&mut self, label: Label) {
+        self.code.push(0xe9);
+        self.fixup(label);
+        self.note("jmp", &[Arg::Label(label)]);
+    }
+
+    pub fn jcc(&mut self, cond: Cond, label: Label) {
+        self.bytes(&[0x0f, 0x80 + cond as u8]);
+        self.fixup(label);
+        self.note(cond.jump(), &[Arg::Label(label)]);
+    }
+
+    fn fixup(&mut self, label: Label) {
+        self.fixups.push((self.code.len(), label));
+        self.bytes(&[0; 4]);
+    }
+
+    /// zero-extends the cell at `mem` into `dst`
+    pub fn load_cell(&mut self, width: Width, dst: Reg, mem: Mem) {
+        match width {
+            Width::Byte => self.modrm(Width::Dword, &[0x0f, 0xb6], dst as u8, Operand::Mem(mem)),
+            Width::Word => self.modrm(Width::Dword, &[0x0f, 0xb7], dst as u8, Operand::Mem(mem)),
+            Width::Dword | Width::Qword => self.modrm(width, &[0x8b], dst as u8, Operand::Mem(mem)),
+        }
+        match width {
+            Width::Byte | Width::Word => self.note(
+                "movzx",
+                &[Arg::Reg(dst, Width::Dword), Arg::Mem(mem, width)],
+            ),
+            Width::Dword | Width::Qword => {
+                self.note("mov", &[Arg::Reg(dst, width), Arg::Mem(mem, width)])
+            }
+        }
+    }
+
+    /// `cmp <width> [mem], 0`
+    pub fn cmp_cell_zero(&mut self, width: Width, mem: Mem) {
+        let opcode = if width == Width::Byte { 0x80 } else { 0x83 };
+        self.modrm(width, &[opcode], 7, Operand::Mem(mem));
+        self.code.push(0);
+        self.note("cmp", &[Arg::Mem(mem, width), Arg::Imm(0)]);
+    }
+
+    /// adds `value`, already truncated to `width`, to the cell at `mem`. Clobbers `rax` for
+    /// 64 bit values that don't fit a sign-extended immediate
+    pub fn add_cell(&mut self, width: Width, mem: Mem, value: u64) {
+        match width {
+            Width::Byte => {
+                self.modrm(width, &[0x80], 0, Operand::Mem(mem));
+                self.code.push(value as u8);
+                self.note_cell("add", width, mem, value);
+            }
+            Width::Word => {
+                self.modrm(width, &[0x81], 0, Operand::Mem(mem));
+                self.bytes(&(value as u16).to_le_bytes());
+                self.note_cell("add", width, mem, value);
+            }
+            Width::Dword => {
+                self.modrm(width, &[0x81], 0, Operand::Mem(mem));
+                self.bytes(&(value as u32).to_le_bytes());
+                self.note_cell("add", width, mem, value);
+            }
+            Width::Qword => match i32::try_from(value as i64) {
+                Ok(imm) => {
+                    self.modrm(width, &[0x81], 0, Operand::Mem(mem));
+                    self.bytes(&imm.to_le_bytes());
+                    self.note_cell("add", width, mem, value);
+                }
+                Err(_) => {
+                    self.mov_imm(Reg::Rax, value);
+                    self.add_cell_reg(width, mem, Reg::Rax);
+                }
+            },
+        }
+    }
+
+    /// stores `value`, already truncated to `width`, in the cell at `mem`. Clobbers `rax` like
+    /// [`Assembler::add_cell`]
+    pub fn set_cell(&mut self, width: Width, mem: Mem, value: u64) {
+        match width {
+            Width::Byte => {
+                self.modrm(width, &[0xc6], 0, Operand::Mem(mem));
+                self.code.push(value as u8);
+                self.note_cell("mov", width, mem, value);
+            }
+            Width::Word => {
+                self.modrm(width, &[0xc7], 0, Operand::Mem(mem));
+                self.bytes(&(value as u16).to_le_bytes());
+                self.note_cell("mov", width, mem, value);
+            }
+            Width::Dword => {
+                self.modrm(width, &[0xc7], 0, Operand::Mem(mem));
+                self.bytes(&(value as u32).to_le_bytes());
+                self.note_cell("mov", width, mem, value);
+            }
+            Width::Qword => match i32::try_from(value as i64) {
+                Ok(imm) => {
+                    self.modrm(width, &[0xc7], 0, Operand::Mem(mem));
+                    self.bytes(&imm.to_le_bytes());
+                    self.note_cell("mov", width, mem, value);
+                }
+                Err(_) => {
+                    self.mov_imm(Reg::Rax, value);
+                    self.store_cell(width, mem, Reg::Rax);
+                }
+            },
+        }
+    }
+
+    /// adds the low `width` bits of `src` to the cell at `mem`
+    pub fn add_cell_reg(&mut self, width: Width, mem: Mem, src: Reg) {
+        let opcode = if width == Width::Byte { 0x00 } else { 0x01 };
+        self.modrm(width, &[opcode], src as u8, Operand::Mem(mem));
+        self.note("add", &[Arg::Mem(mem, width), Arg::Reg(src, width)]);
+    }
+
+    /// stores the low `width` bits of `src` in the cell at `mem`
+    pub fn store_cell(&mut self, width: Width, mem: Mem, src: Reg) {
+        let opcode = if width == Width::Byte { 0x88 } else { 0x89 };
+        self.modrm(width, &[opcode], src as u8, Operand::Mem(mem));
+        self.note("mov", &[Arg::Mem(mem, width), Arg::Reg(src, width)]);
+    }
+
+    /// lists `mnemonic <width> [mem], value` for an instruction with an immediate cell value,
+    /// adds read as signed so they show which way they go
+    fn note_cell(&mut self, mnemonic: &'static str, width: Width, mem: Mem, value: u64) {
+        let value = if mnemonic == "add" {
+            width.sign_extend(value)
+        } else {
+            value as i64
+        };
+        self.note(mnemonic, &[Arg::Mem(mem, width), Arg::Imm(value)]);
+    }
+}
+
+#[test]
+fn encodes_instructions() {
+    let mut asm = Assembler::new();
+    let top = asm.new_label();
+    asm.bind(top);
+    asm.push(Reg::R12);
+    asm.load(Reg::Rbx, Mem::at(Reg::R14, 8));
+    asm.lea(Reg::Rdx, Mem::at(Reg::R12, -3));
+    asm.add_cell(Width::Byte, Mem::at(Reg::R12, 0), 0xfd);
+    asm.set_cell(Width::Word, Mem::at(Reg::R13, 0), 7);
+    asm.cmp_cell_zero(Width::Qword, Mem::at(Reg::Rdx, 0x100));
+    asm.mov_imm(Reg::Rax, 1 << 40);
+    asm.jcc(Cond::NotEqual, top);
+    let code = asm.finish();
+    #[rustfmt::skip]
+    let expected = [
+        0x41, 0x54,                                     // push r12
+        0x49, 0x8b, 0x5e, 0x08,                         // mov rbx, [r14 + 8]
+        0x49, 0x8d, 0x54, 0x24, 0xfd,                   // lea rdx, [r12 - 3]
+        0x41, 0x80, 0x04, 0x24, 0xfd,                   // add byte [r12], 0xfd
+        0x66, 0x41, 0xc7, 0x45, 0x00, 0x07, 0x00,       // mov word [r13], 7
+        0x48, 0x83, 0xba, 0x00, 0x01, 0x00, 0x00, 0x00, // cmp qword [rdx + 0x100], 0
+        0x48, 0xb8, 0, 0, 0, 0, 0, 1, 0, 0,             // mov rax, 1 << 40
+        0x0f, 0x85, 0xd1, 0xff, 0xff, 0xff,             // jne top
+    ];
+    assert_eq!(code, expected);
+}
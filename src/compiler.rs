@@ -1,202 +1,828 @@
-use super::*; 
+use super::*;
+mod error;
+mod fold;
+mod ir;
+mod options;
+mod source;
+pub use error::*;
+use fold::ConstantFolder;
+pub use fold::DEFAULT_FOLD_BUDGET;
+use ir::Node;
+pub use options::*;
+pub use source::*;
 
 pub struct Compiler;
 
 impl Compiler{
     /// # Description
     /// parses brainfuck source into tokens used by the runtime (or compiler if I get there)
-    pub fn compile(source: &str) -> Vec<ByteCode> {
+    /// optimized code assumes the program starts on a zeroed tape
+    pub fn compile(source: &str) -> Result<Vec<ByteCode>, CompileError> {
+        Self::compile_with_source_map(source).map(|(output, _)| output)
+    }
 
-        let source = Self::strip_source_of_whitespace_and_comments(source);
-        // println!("stripped-source:\"{}\"", source);
-        let mut output = vec![ByteCode::NOP; source.len()];
-        let unoptimized_instructions = output.len();
-        Self::tokenize_string(&source, &mut output);
+    /// # Description
+    /// same as [`Compiler::compile`] but also returns a [`SourceMap`] that maps every emitted
+    /// instruction back to the range of original source it was built from
+    pub fn compile_with_source_map(source: &str) -> Result<(Vec<ByteCode>, SourceMap), CompileError> {
+        Self::compile_with_options(source, &CompileOptions::default())
+            .map(|output| (output.bytecode, output.source_map))
+    }
 
-        Self::optimize_instructions(&mut output);
-        let optimized_intructions = output.len();
+    /// # Description
+    /// same as [`Compiler::compile`] but also folds the start of the program into constants and
+    /// drops the code after the program's last output that only changes the tape. Use this when
+    /// nothing looks at the tape once the program ends, pointer errors the dropped code would
+    /// have run into go away along with it
+    pub fn compile_for_output(source: &str) -> Result<Vec<ByteCode>, CompileError> {
+        Self::compile_for_output_with_source_map(source).map(|(output, _)| output)
+    }
 
-        Self::reindex_branches(&mut output);
-        Self::compute_bracket_indexes(&mut output);
+    pub fn compile_for_output_with_source_map(
+        source: &str,
+    ) -> Result<(Vec<ByteCode>, SourceMap), CompileError> {
+        Self::compile_with_options(source, &CompileOptions::level(3))
+            .map(|output| (output.bytecode, output.source_map))
+    }
 
-        println!(
-            "unoptimized = '{}', optimized = '{}'",
-            unoptimized_instructions, optimized_intructions
-        );
-        output
+    pub fn compile_unoptimized(source: &str) -> Result<Vec<ByteCode>, CompileError> {
+        Self::compile_unoptimized_with_source_map(source).map(|(output, _)| output)
     }
 
-    pub fn compile_unoptimized(source: &str) -> Vec<ByteCode> {
-        let source = Self::strip_source_of_whitespace_and_comments(source);
-        // println!("stripped-source:\"{}\"", source);
-        let mut output = vec![ByteCode::NOP; source.len()];
-        Self::tokenize_string(&source, &mut output);
-        // Self::reindex_branches(&mut output);
-        Self::compute_bracket_indexes(&mut output);
-        output
+    pub fn compile_unoptimized_with_source_map(
+        source: &str,
+    ) -> Result<(Vec<ByteCode>, SourceMap), CompileError> {
+        Self::compile_with_options(source, &CompileOptions::level(0))
+            .map(|output| (output.bytecode, output.source_map))
     }
 
+    /// # Description
+    /// compiles `source` running the passes `options` lists, in order. Along with the code and
+    /// its source map this returns what each pass did
+    pub fn compile_with_options(
+        source: &str,
+        options: &CompileOptions,
+    ) -> Result<CompileOutput, CompileError> {
+        let source =
+            Self::strip_source_of_whitespace_and_comments(source, options.comment_syntax())?;
+        let mut nodes = Node::parse(&source)?;
 
-    /// uses statemachine logic to
-    fn optimize_instructions(unoptimized_code: &mut Vec<ByteCode>) {
-        let mut optimized_instructions = vec![];
-        let mut accum_counter = 0;
+        let mut statistics = Vec::with_capacity(options.passes().len());
+        for &pass in options.passes() {
+            let instructions_before = Node::instruction_count(&nodes);
+            let t0 = Instant::now();
+            pass.run(&mut nodes, options);
+            statistics.push(PassStatistics {
+                pass,
+                instructions_before,
+                instructions_after: Node::instruction_count(&nodes),
+                duration: t0.elapsed(),
+            });
+        }
 
-        //to solve the last token problem
-        unoptimized_code.push(ByteCode::NOP);
-        let mut current_instruction = unoptimized_code[0];
+        let (bytecode, spans) = Node::lower(&nodes);
+        Ok(CompileOutput {
+            bytecode,
+            source_map: SourceMap::new(spans),
+            statistics,
+        })
+    }
 
-        let push_unoptimizable_inst = |inst, optimized_instructions: &mut Vec<_>| {
-            if let ByteCode::InputByte
-            | ByteCode::OutputByte
-            | ByteCode::LoopClose { .. }
-            | ByteCode::LoopOpen { .. } = inst
-            {
-                optimized_instructions.push(inst);
+    /// calls `rewrite` on every block of the tree, loop bodies before the block holding them
+    fn rewrite_blocks<F: FnMut(&mut Vec<Node>)>(block: &mut Vec<Node>, rewrite: &mut F) {
+        for node in block.iter_mut() {
+            if let Node::Loop { body, .. } = node {
+                Self::rewrite_blocks(body, rewrite);
             }
-        };
+        }
+        rewrite(block);
+    }
 
-        for &mut inst in unoptimized_code.iter_mut() {
-            match current_instruction {
-                ByteCode::IncrementByte => match inst {
-                    ByteCode::IncrementByte => {
-                        accum_counter += 1;
-                    }
-                    _ => {
-                        optimized_instructions.push(ByteCode::QuickIncrementByte(accum_counter));
-                        current_instruction = inst;
-                        accum_counter = 1;
-                        push_unoptimizable_inst(inst, &mut optimized_instructions);
+    /// folds runs of `+`/`-` and `>`/`<` into a single signed `AddByte` or `MoveDataPtr`,
+    /// dropping runs that cancel out. Only wrapping cells let a run change direction, with
+    /// [`CellOverflow::Saturating`] or [`CellOverflow::Trap`] a `+-` on a full cell has to clamp
+    /// or trap halfway through, so `+` and `-` end up in runs of their own. Folded instructions
+    /// cover the whole run they replaced in the source map
+    pub(crate) fn optimize_instructions(nodes: &mut Vec<Node>, cell_overflow: CellOverflow) {
+        let wraps = cell_overflow == CellOverflow::Wrapping;
+        Self::rewrite_blocks(nodes, &mut |block| {
+            let mut folded = Vec::with_capacity(block.len());
+            //whether the run moves the pointer, how far it goes and where it came from
+            let mut run: Option<(bool, isize, SourceSpan)> = None;
+            for node in block.drain(..) {
+                match node {
+                    Node::Op { op, span } if Self::step(op) != 0 => {
+                        let moves_pointer = Self::pointer_delta(op) != 0;
+                        match &mut run {
+                            Some((run_moves_pointer, amount, run_span))
+                                if *run_moves_pointer == moves_pointer
+                                    && (wraps || amount.signum() == Self::step(op).signum()) =>
+                            {
+                                *amount += Self::step(op);
+                                *run_span = run_span.merge(span);
+                            }
+                            _ => {
+                                Self::push_run(run, &mut folded);
+                                run = Some((moves_pointer, Self::step(op), span));
+                            }
+                        }
                     }
-                },
-                ByteCode::DecrementByte => match inst {
-                    ByteCode::DecrementByte => {
-                        accum_counter += 1;
+                    node => {
+                        Self::push_run(run.take(), &mut folded);
+                        folded.push(node);
                     }
+                }
+            }
+            Self::push_run(run, &mut folded);
+            *block = folded;
+        });
+    }
+
+    fn push_run(run: Option<(bool, isize, SourceSpan)>, block: &mut Vec<Node>) {
+        match run {
+            //runs that cancel out entirely leave nothing behind
+            Some((_, 0, _)) | None => {}
+            Some((true, amount, span)) => block.push(Node::op(ByteCode::MoveDataPtr(amount), span)),
+            Some((false, amount, span)) => block.push(Node::op(ByteCode::AddByte(amount), span)),
+        }
+    }
+
+    /// deletes loops that can never run because the current cell is known to be zero when they're
+    /// reached. That's the case before anything has been written to the tape, where comment
+    /// blocks like `[ this program ... ]` usually sit, and straight after another loop's `]`
+    pub(crate) fn eliminate_dead_loops(nodes: &mut Vec<Node>) {
+        //programs start on a zeroed tape, so until something is stored every cell is zero
+        Self::eliminate_dead_loops_in(nodes, true);
+    }
+
+    fn eliminate_dead_loops_in(block: &mut Vec<Node>, mut tape_untouched: bool) {
+        //loop bodies only run while the cell they start on isn't zero
+        let mut cell_zero = tape_untouched;
+        block.retain_mut(|node| {
+            match node {
+                Node::Loop { .. } if cell_zero => return false,
+                Node::Loop { body, .. } => {
+                    Self::eliminate_dead_loops_in(body, false);
+                    tape_untouched = false;
+                    cell_zero = true;
+                }
+                Node::Op { op, .. } => match op {
+                    ByteCode::IncrementDataPtr
+                    | ByteCode::DecrementDataPtr
+                    | ByteCode::MoveDataPtr(_) => cell_zero = tape_untouched,
+                    ByteCode::OutputByte | ByteCode::OutputConst(_) | ByteCode::NOP => {}
                     _ => {
-                        optimized_instructions.push(ByteCode::QuickDecrementByte(accum_counter));
-                        current_instruction = inst;
-                        accum_counter = 1;
-                        push_unoptimizable_inst(inst, &mut optimized_instructions);
+                        tape_untouched = false;
+                        cell_zero = false;
                     }
                 },
-                ByteCode::IncrementDataPtr => match inst {
-                    ByteCode::IncrementDataPtr => {
-                        accum_counter += 1;
-                    }
-                    _ => {
-                        optimized_instructions
-                            .push(ByteCode::QuickIncrementDataPtr(accum_counter));
-                        current_instruction = inst;
-                        accum_counter = 1;
+            }
+            true
+        });
+    }
 
-                        push_unoptimizable_inst(inst, &mut optimized_instructions);
-                    }
-                },
-                ByteCode::DecrementDataPtr => match inst {
-                    ByteCode::DecrementDataPtr => {
-                        accum_counter += 1;
-                    }
-                    _ => {
-                        optimized_instructions
-                            .push(ByteCode::QuickDecrementDataPtr(accum_counter));
-                        current_instruction = inst;
-                        accum_counter = 1;
-                        push_unoptimizable_inst(inst, &mut optimized_instructions);
+    /// runs as much of the start of the program as it can at compile time and replaces it with
+    /// the output it produced and the tape it left behind. Folding stops before the first
+    /// top-level loop or instruction that reads input, pushes a cell outside `0..=255`, moves
+    /// the pointer left of where it started or doesn't finish within `budget` steps
+    pub(crate) fn fold_constants(nodes: &mut Vec<Node>, budget: u64) {
+        let mut folder = ConstantFolder::new(budget);
+        let folded = nodes
+            .iter()
+            .take_while(|node| folder.run(node).is_some())
+            .count();
+        if folded == 0 {
+            return;
+        }
+        if folded < nodes.len() {
+            //the node that couldn't be folded left the tape half updated, redo the ones that were
+            folder = ConstantFolder::new(budget);
+            for node in &nodes[..folded] {
+                folder.run(node);
+            }
+        }
+        let span = nodes[0].span().merge(nodes[folded - 1].span());
+        nodes.splice(..folded, folder.into_nodes(span));
+    }
+
+    /// drops everything after the last instruction with an effect outside the tape. Output and
+    /// input are observable, loops and scans stay as well because they might never finish
+    pub(crate) fn eliminate_trailing_code(nodes: &mut Vec<Node>) {
+        let len = nodes
+            .iter()
+            .rposition(|node| match node {
+                Node::Loop { .. } => true,
+                Node::Op { op, .. } => matches!(
+                    op,
+                    ByteCode::OutputByte
+                        | ByteCode::OutputByteAt(_)
+                        | ByteCode::OutputConst(_)
+                        | ByteCode::InputByte
+                        | ByteCode::InputByteAt(_)
+                        | ByteCode::Scan(_)
+                ),
+            })
+            .map_or(0, |idx| idx + 1);
+        nodes.truncate(len);
+    }
+
+    /// replaces clear loops (`[-]`, and `[+]` when cells wrap) with a single `SetByte`. A
+    /// `+`/`-` run straight after the loop is folded into the value being stored. `[+]` only
+    /// reaches zero by wrapping, so it's left alone for saturating and trapping cells
+    pub(crate) fn optimize_clear_loops(nodes: &mut Vec<Node>, cell_overflow: CellOverflow) {
+        let wraps = cell_overflow == CellOverflow::Wrapping;
+        Self::rewrite_blocks(nodes, &mut |block| {
+            let mut optimized = Vec::with_capacity(block.len());
+            let mut nodes = block.drain(..).peekable();
+            while let Some(node) = nodes.next() {
+                let clear_loop = node
+                    .single_op_loop()
+                    .map(Self::byte_delta)
+                    .is_some_and(|delta| delta == -1 || (wraps && delta == 1));
+                if !clear_loop {
+                    optimized.push(node);
+                    continue;
+                }
+                let (value, span) = Self::take_following_add(&mut nodes, node.span());
+                optimized.push(Node::op(ByteCode::SetByte(value), span));
+            }
+            drop(nodes);
+            *block = optimized;
+        });
+    }
+
+    /// replaces multiply loops with a `MultiplyAdd` for every cell they add to followed by a
+    /// `SetByte(0)`. A multiply loop has no nested loops or I/O, ends on the cell it started
+    /// on and decrements that cell by exactly one per iteration, e.g. `[->+>++<<]`
+    pub(crate) fn optimize_multiply_loops(nodes: &mut Vec<Node>, cell_overflow: CellOverflow) {
+        let wraps = cell_overflow == CellOverflow::Wrapping;
+        Self::rewrite_blocks(nodes, &mut |block| {
+            let mut optimized = Vec::with_capacity(block.len());
+            let mut nodes = block.drain(..).peekable();
+            while let Some(node) = nodes.next() {
+                let Some(targets) = Self::multiply_loop_targets(&node, wraps) else {
+                    optimized.push(node);
+                    continue;
+                };
+                let span = node.span();
+                for (offset, factor) in targets {
+                    let op = ByteCode::MultiplyAdd {
+                        source: 0,
+                        offset,
+                        factor,
+                    };
+                    optimized.push(Node::op(op, span));
+                }
+                //the loop leaves its cell at zero, fold whatever gets added to it next into that
+                let (value, span) = Self::take_following_add(&mut nodes, span);
+                optimized.push(Node::op(ByteCode::SetByte(value), span));
+            }
+            drop(nodes);
+            *block = optimized;
+        });
+    }
+
+    /// if `node` is a multiply loop, returns the `(offset, factor)` of every cell it adds to.
+    /// Unless cells `wrap` every cell has to go one way only, adding to a cell and taking from
+    /// it again could clamp or trap in between
+    fn multiply_loop_targets(node: &Node, wraps: bool) -> Option<Vec<(isize, isize)>> {
+        let Node::Loop { body, .. } = node else {
+            return None;
+        };
+        let mut offset = 0;
+        //kept in the order cells are first touched so the output is deterministic
+        let mut deltas: Vec<(isize, isize)> = vec![];
+        for node in body {
+            let Node::Op { op, .. } = *node else {
+                return None;
+            };
+            match op {
+                ByteCode::IncrementDataPtr
+                | ByteCode::DecrementDataPtr
+                | ByteCode::MoveDataPtr(_) => offset += Self::pointer_delta(op),
+                ByteCode::IncrementByte | ByteCode::DecrementByte | ByteCode::AddByte(_) => {
+                    let delta = Self::byte_delta(op);
+                    match deltas.iter_mut().find(|(target, _)| *target == offset) {
+                        Some((_, factor)) if !wraps && factor.signum() != delta.signum() => {
+                            return None
+                        }
+                        Some((_, factor)) => *factor += delta,
+                        None => deltas.push((offset, delta)),
                     }
-                },
-                _ => {
-                    accum_counter = 1;
-                    current_instruction = inst;
-                    push_unoptimizable_inst(inst, &mut optimized_instructions);
                 }
+                _ => return None,
             }
         }
-        //transfer optimized code to unoptimized buffer
-        unoptimized_code.clear();
-        for inst in optimized_instructions {
-            unoptimized_code.push(inst);
+        let loop_cell = deltas.iter().position(|&(target, _)| target == 0)?;
+        if offset != 0 || deltas.remove(loop_cell).1 != -1 {
+            return None;
         }
+        deltas.retain(|&(_, factor)| factor != 0);
+        Some(deltas)
     }
 
-    fn reindex_branches(code: &mut Vec<ByteCode>) {
-        for (idx, inst) in code.iter_mut().enumerate() {
-            match inst {
-                ByteCode::LoopClose { open_location } => {
-                    *open_location = idx;
-                }
-                ByteCode::LoopOpen { close_location } => {
-                    *close_location = idx;
+    /// if the next node adds to the current cell, takes it out and returns how much it adds
+    /// along with `span` widened to cover it
+    fn take_following_add<I: Iterator<Item = Node>>(
+        nodes: &mut std::iter::Peekable<I>,
+        span: SourceSpan,
+    ) -> (isize, SourceSpan) {
+        match nodes.peek() {
+            Some(&Node::Op { op, span: add_span }) if Self::byte_delta(op) != 0 => {
+                nodes.next();
+                (Self::byte_delta(op), span.merge(add_span))
+            }
+            _ => (0, span),
+        }
+    }
+
+    /// replaces scan loops like `[>]` or `[<<<]` with a single `Scan`
+    pub(crate) fn optimize_scan_loops(nodes: &mut Vec<Node>) {
+        Self::rewrite_blocks(nodes, &mut |block| {
+            for node in block.iter_mut() {
+                let stride = node.single_op_loop().map_or(0, Self::pointer_delta);
+                if stride != 0 {
+                    *node = Node::op(ByteCode::Scan(stride), node.span());
                 }
-                _ => (),
             }
+        });
+    }
+
+    /// rewrites every stretch of code between loops into instructions addressing cells relative
+    /// to where the pointer was when the stretch began, followed by a single move for the net
+    /// distance the pointer travelled. Pointer moves that never touch a cell off the tape can no
+    /// longer fail on their own once they're folded together
+    pub(crate) fn optimize_pointer_movement(nodes: &mut Vec<Node>) {
+        Self::rewrite_blocks(nodes, &mut |block| {
+            let mut optimized = Vec::with_capacity(block.len());
+            let mut offset = 0;
+            let mut move_span: Option<SourceSpan> = None;
+            for node in block.drain(..) {
+                let relative = match node {
+                    Node::Op { op, span } => match op {
+                        ByteCode::IncrementDataPtr
+                        | ByteCode::DecrementDataPtr
+                        | ByteCode::MoveDataPtr(_) => {
+                            offset += Self::pointer_delta(op);
+                            move_span =
+                                Some(move_span.map_or(span, |move_span| move_span.merge(span)));
+                            continue;
+                        }
+                        ByteCode::IncrementByte
+                        | ByteCode::DecrementByte
+                        | ByteCode::AddByte(_) => {
+                            let delta = Self::byte_delta(op);
+                            Node::op(ByteCode::AddByteAt { offset, delta }, span)
+                        }
+                        ByteCode::SetByte(value) => {
+                            Node::op(ByteCode::SetByteAt { offset, value }, span)
+                        }
+                        ByteCode::MultiplyAdd {
+                            source,
+                            offset: target,
+                            factor,
+                        } => {
+                            let op = ByteCode::MultiplyAdd {
+                                source: source + offset,
+                                offset: target + offset,
+                                factor,
+                            };
+                            Node::op(op, span)
+                        }
+                        ByteCode::OutputByte => Node::op(ByteCode::OutputByteAt(offset), span),
+                        ByteCode::InputByte => Node::op(ByteCode::InputByteAt(offset), span),
+                        _ => {
+                            Self::flush_pointer_movement(
+                                &mut offset,
+                                &mut move_span,
+                                &mut optimized,
+                            );
+                            node
+                        }
+                    },
+                    //loops depend on where the pointer is, it has to be there first
+                    Node::Loop { .. } => {
+                        Self::flush_pointer_movement(&mut offset, &mut move_span, &mut optimized);
+                        node
+                    }
+                };
+                optimized.push(relative);
+            }
+            Self::flush_pointer_movement(&mut offset, &mut move_span, &mut optimized);
+            *block = optimized;
+        });
+    }
+
+    /// emits the pointer move a stretch of offset-addressed code has been putting off
+    fn flush_pointer_movement(
+        offset: &mut isize,
+        move_span: &mut Option<SourceSpan>,
+        block: &mut Vec<Node>,
+    ) {
+        if let Some(span) = move_span.take() {
+            if *offset != 0 {
+                block.push(Node::op(ByteCode::MoveDataPtr(*offset), span));
+            }
+        }
+        *offset = 0;
+    }
+
+    /// how far `inst` moves the data pointer
+    fn pointer_delta(inst: ByteCode) -> isize {
+        match inst {
+            ByteCode::IncrementDataPtr => 1,
+            ByteCode::DecrementDataPtr => -1,
+            ByteCode::MoveDataPtr(amount) => amount,
+            _ => 0,
+        }
+    }
+
+    /// how far a single-step instruction moves the pointer or changes the current cell
+    fn step(inst: ByteCode) -> isize {
+        match inst {
+            ByteCode::IncrementByte | ByteCode::IncrementDataPtr => 1,
+            ByteCode::DecrementByte | ByteCode::DecrementDataPtr => -1,
+            _ => 0,
         }
     }
 
-    fn strip_source_of_whitespace_and_comments(source: &str) -> String {
+    /// the amount `inst` adds to the current cell if it's a `+`/`-` run, zero otherwise
+    fn byte_delta(inst: ByteCode) -> isize {
+        match inst {
+            ByteCode::AddByte(amount) => amount,
+            ByteCode::IncrementByte => 1,
+            ByteCode::DecrementByte => -1,
+            _ => 0,
+        }
+    }
+
+    /// strips whitespace and comments while remembering where every surviving command came from
+    fn strip_source_of_whitespace_and_comments(
+        source: &str,
+        comment_syntax: CommentSyntax,
+    ) -> Result<Vec<(char, SourceLocation)>, CompileError> {
+        let is_command = |c| matches!(c, '>' | '<' | '+' | '-' | '.' | ',' | '[' | ']');
         source
             .lines()
-            .flat_map(|line| {
+            .enumerate()
+            .flat_map(|(line_idx, line)| {
                 line.chars()
-                    .filter(|c| !c.is_whitespace())
-                    .take_while(|&c| c != '#')
+                    .enumerate()
+                    .take_while(move |&(_, c)| {
+                        c != '#' || comment_syntax != CommentSyntax::HashLineComments
+                    })
+                    .map(move |(column_idx, c)| {
+                        (c, SourceLocation::new(line_idx + 1, column_idx + 1))
+                    })
             })
-            .collect::<String>()
-    }
-
-    fn tokenize_string(source: &str, output: &mut [ByteCode]) {
-        for (idx, (c, inst)) in source.chars().zip(output.iter_mut()).enumerate() {
-            *inst = match c {
-                '>' => ByteCode::IncrementDataPtr,
-                '<' => ByteCode::DecrementDataPtr,
-                '+' => ByteCode::IncrementByte,
-                '-' => ByteCode::DecrementByte,
-                ',' => ByteCode::InputByte,
-                '.' => ByteCode::OutputByte,
-                //store instruction location for bracket tokens
-                '[' => ByteCode::LoopOpen {
-                    close_location: idx,
-                },
-                ']' => ByteCode::LoopClose { open_location: idx },
-                _ => ByteCode::NOP,
-            };
+            .filter_map(|(c, location)| match comment_syntax {
+                _ if is_command(c) => Some(Ok((c, location))),
+                CommentSyntax::Strict if !c.is_whitespace() => {
+                    Some(Err(CompileError::UnexpectedCharacter(c, location)))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+}
+#[test]
+fn unmatched_close_bracket_is_located() {
+    let source = "++[-]\n  +]";
+    let error = Compiler::compile(source).unwrap_err();
+    assert_eq!(error, CompileError::UnmatchedCloseBracket(SourceLocation::new(2, 4)));
+}
+
+#[test]
+fn unmatched_open_bracket_is_located() {
+    let source = "+[ #comment ]\n[-]";
+    let options = CompileOptions::level(0).with_comment_syntax(CommentSyntax::HashLineComments);
+    let error = Compiler::compile_with_options(source, &options).unwrap_err();
+    assert_eq!(error, CompileError::UnmatchedOpenBracket(SourceLocation::new(1, 2)));
+}
+
+#[test]
+fn source_map_covers_fused_instructions() {
+    let source = "++\n+ >\n[-]";
+    let (bytecode, source_map) = Compiler::compile_with_source_map(source).unwrap();
+    assert_eq!(bytecode.len(), source_map.len());
+    // "++\n+" fuses into a single AddByte spanning two lines
+    assert_eq!(
+        source_map.span(0),
+        Some(SourceSpan::new(SourceLocation::new(1, 1), SourceLocation::new(2, 1)))
+    );
+    // "[-]" is cleared with a single SetByte covering the whole loop
+    assert_eq!(
+        source_map.span(1),
+        Some(SourceSpan::new(SourceLocation::new(3, 1), SourceLocation::new(3, 3)))
+    );
+    // the ">" it addresses through is moved past at the end
+    assert_eq!(source_map.span(2), Some(SourceSpan::at(SourceLocation::new(2, 3))));
+    assert_eq!(source_map.span(3), None);
+}
+
+#[test]
+fn clear_loops_become_set_byte() {
+    let bytecode = Compiler::compile(",>[-]+++<[+]--[-]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::InputByteAt(0),
+            ByteCode::SetByteAt {
+                offset: 1,
+                value: 3
+            },
+            ByteCode::SetByteAt {
+                offset: 0,
+                value: -2
+            },
+            ByteCode::SetByteAt {
+                offset: 0,
+                value: 0
+            },
+        ]
+    ));
+    // only single steps clear a cell, `[--]` never ends on odd values
+    let bytecode = Compiler::compile(",[--]").unwrap();
+    assert!(matches!(
+        bytecode[2],
+        ByteCode::AddByteAt {
+            offset: 0,
+            delta: -2
         }
+    ));
+    // `[+]` only clears a cell by wrapping around, which not every cell does
+    for cell_overflow in [CellOverflow::Saturating, CellOverflow::Trap] {
+        let options = CompileOptions::level(2).with_cell_overflow(cell_overflow);
+        let bytecode = Compiler::compile_with_options(",[+]", &options)
+            .unwrap()
+            .bytecode;
+        assert!(matches!(bytecode[1], ByteCode::LoopOpen { .. }));
     }
+}
 
-    fn compute_bracket_indexes(output: &mut [ByteCode]) {
-        //basically i use a bracket stack to detect matching brackets
-        //when matching brackets are detected I update index info
-        let mut bracket_stack = Vec::new();
-        output
-            .iter_mut()
-            .filter(|inst| {
-                if let ByteCode::LoopOpen { .. } | ByteCode::LoopClose { .. } = inst {
-                    true
-                } else {
-                    false
-                }
-            })
-            .for_each(|inst| match inst {
-                ByteCode::LoopOpen { .. } => {
-                    bracket_stack.push(inst);
-                }
-                ByteCode::LoopClose { open_location } => {
-                    let matching_open_token = bracket_stack.pop().expect("mismatching brackets");
+#[test]
+fn multiply_loops_become_multiply_add() {
+    let bytecode = Compiler::compile(",[->+>+++<<-<--->+]+").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::InputByteAt(0),
+            ByteCode::MultiplyAdd {
+                source: 0,
+                offset: 1,
+                factor: 1
+            },
+            ByteCode::MultiplyAdd {
+                source: 0,
+                offset: 2,
+                factor: 3
+            },
+            ByteCode::MultiplyAdd {
+                source: 0,
+                offset: -1,
+                factor: -3
+            },
+            ByteCode::SetByteAt {
+                offset: 0,
+                value: 1
+            },
+        ]
+    ));
+    // the loop cell has to go down by exactly one and the pointer has to end where it started
+    for source in [",[-->+<]", ",[->+]", ",[->+<.]", ",[->[-]<]"] {
+        let bytecode = Compiler::compile(source).unwrap();
+        assert!(
+            matches!(bytecode[1], ByteCode::LoopOpen { .. }),
+            "{}",
+            source
+        );
+    }
+}
 
-                    //save instruction location for close location
-                    let cached_close_location = *open_location;
+#[test]
+fn scan_loops_become_scan() {
+    let bytecode = Compiler::compile("+[>]<[<<<<<<<<<]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::AddByteAt {
+                offset: 0,
+                delta: 1
+            },
+            ByteCode::Scan(1),
+            ByteCode::MoveDataPtr(-1),
+            ByteCode::Scan(-9),
+        ]
+    ));
+}
 
-                    //write open location to close location
-                    *open_location = matching_open_token
-                        .bracket_location()
-                        .expect("matching open token should be a bracket");
+#[test]
+fn pointer_moves_become_offsets() {
+    let bytecode = Compiler::compile(">+>>--<.[-<,>]>>[->+<]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::AddByteAt {
+                offset: 1,
+                delta: 1
+            },
+            ByteCode::AddByteAt {
+                offset: 3,
+                delta: -2
+            },
+            ByteCode::OutputByteAt(2),
+            ByteCode::MoveDataPtr(2),
+            ByteCode::LoopOpen { .. },
+            ByteCode::AddByteAt {
+                offset: 0,
+                delta: -1
+            },
+            ByteCode::InputByteAt(-1),
+            ByteCode::LoopClose { .. },
+            ByteCode::MultiplyAdd {
+                source: 2,
+                offset: 3,
+                factor: 1
+            },
+            ByteCode::SetByteAt {
+                offset: 2,
+                value: 0
+            },
+            ByteCode::MoveDataPtr(2),
+        ]
+    ));
+    // moves that cancel out leave nothing behind
+    let bytecode = Compiler::compile(">>+<<").unwrap();
+    assert_eq!(bytecode.len(), 1);
+}
 
-                    //write open location to close location tag
-                    if let ByteCode::LoopOpen { close_location } = matching_open_token {
-                        *close_location = cached_close_location;
-                    }
-                }
-                _ => (),
-            });
+#[test]
+fn mixed_runs_cancel() {
+    let bytecode = Compiler::compile("+++--><<>").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [ByteCode::AddByteAt {
+            offset: 0,
+            delta: 1
+        }]
+    ));
+    let bytecode = Compiler::compile(",+-[<>-+>]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [ByteCode::InputByteAt(0), ByteCode::Scan(1)]
+    ));
+}
+
+#[test]
+fn dead_loops_are_removed() {
+    let bytecode = Compiler::compile("[a comment, with . and ,]>>[more]+[-][.][[.]],[.]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::AddByteAt {
+                offset: 2,
+                delta: 1
+            },
+            ByteCode::SetByteAt {
+                offset: 2,
+                value: 0
+            },
+            ByteCode::InputByteAt(2),
+            ByteCode::MoveDataPtr(2),
+            ByteCode::LoopOpen { .. },
+            ByteCode::OutputByteAt(0),
+            ByteCode::LoopClose { .. },
+        ]
+    ));
+}
+
+#[test]
+fn trailing_code_is_removed() {
+    let bytecode = Compiler::compile_for_output(",+.>+<[-]").unwrap();
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::InputByteAt(0),
+            ByteCode::AddByteAt { .. },
+            ByteCode::OutputByteAt(0)
+        ]
+    ));
+    // input, loops and scans are kept since they're observable or might not finish
+    for source in [",+.,+>", ",+.[+>]-", ",+.[>]+"] {
+        let bytecode = Compiler::compile_for_output(source).unwrap();
+        assert!(
+            matches!(
+                bytecode.last(),
+                Some(ByteCode::InputByteAt(_) | ByteCode::LoopClose { .. } | ByteCode::Scan(_))
+            ),
+            "{}",
+            source
+        );
+    }
+    assert_eq!(Compiler::compile(",+.>+<[-]").unwrap().len(), 5);
+}
+
+#[test]
+fn passes_can_be_picked_and_ordered() {
+    let source = ",[>]+++---[-]";
+    let output = Compiler::compile_with_options(source, &CompileOptions::level(1)).unwrap();
+    assert_eq!(output.statistics.len(), 1);
+    assert_eq!(output.statistics[0].pass, Pass::FoldRuns);
+    assert_eq!(output.statistics[0].instructions_before, 13);
+    assert_eq!(output.bytecode.len(), 7);
+
+    let options = CompileOptions::default().disable(Pass::ScanLoops);
+    let output = Compiler::compile_with_options(source, &options).unwrap();
+    assert!(output
+        .statistics
+        .iter()
+        .all(|stats| stats.pass != Pass::ScanLoops));
+    assert!(matches!(output.bytecode[1], ByteCode::LoopOpen { .. }));
+
+    // clear loops are recognised in single steps too, so folding isn't required first
+    let options = CompileOptions::with_passes([Pass::ClearLoops, Pass::FoldRuns]);
+    let output = Compiler::compile_with_options(source, &options).unwrap();
+    assert!(matches!(
+        output.bytecode[..],
+        [
+            ByteCode::InputByte,
+            ByteCode::LoopOpen { .. },
+            ByteCode::MoveDataPtr(1),
+            ByteCode::LoopClose { .. },
+            ByteCode::SetByte(0),
+        ]
+    ));
+    assert_eq!(output.statistics[0].removed(), 2);
+}
+
+#[test]
+fn comment_syntaxes() {
+    let source = "+# the rest of the line is a comment +\n.";
+    let compile = |comment_syntax| {
+        let options = CompileOptions::level(1).with_comment_syntax(comment_syntax);
+        Compiler::compile_with_options(source, &options).map(|output| output.bytecode)
+    };
+    let standard = compile(CommentSyntax::Standard).unwrap();
+    assert!(matches!(
+        standard[..],
+        [ByteCode::AddByte(2), ByteCode::OutputByte]
+    ));
+    let hash = compile(CommentSyntax::HashLineComments).unwrap();
+    assert!(matches!(
+        hash[..],
+        [ByteCode::AddByte(1), ByteCode::OutputByte]
+    ));
+    assert_eq!(
+        compile(CommentSyntax::Strict).unwrap_err(),
+        CompileError::UnexpectedCharacter('#', SourceLocation::new(1, 2))
+    );
+
+    let options = CompileOptions::level(1).with_comment_syntax(CommentSyntax::Strict);
+    assert!(Compiler::compile_with_options(" +\t+ \n\r\n. ", &options).is_ok());
+}
+
+#[test]
+fn preludes_are_folded() {
+    let fold = |source, budget| {
+        let options = CompileOptions::with_passes([Pass::FoldRuns, Pass::ConstantFolding])
+            .with_fold_budget(budget);
+        Compiler::compile_with_options(source, &options)
+            .unwrap()
+            .bytecode
+    };
+    let bytecode = fold("++++++++[>++++++++<-]>+.>++<<,", 1000);
+    assert!(matches!(
+        bytecode[..],
+        [
+            ByteCode::OutputConst(b'A'),
+            ByteCode::SetByteAt {
+                offset: 1,
+                value: 65
+            },
+            ByteCode::SetByteAt {
+                offset: 2,
+                value: 2
+            },
+            ByteCode::InputByte,
+        ]
+    ));
+    // the loop runs out of budget, so only the first run gets folded
+    let bytecode = fold("++++++++[>++++++++<-]>+.", 20);
+    assert!(matches!(
+        bytecode[0],
+        ByteCode::SetByteAt {
+            offset: 0,
+            value: 8
+        }
+    ));
+    assert!(matches!(bytecode[1], ByteCode::LoopOpen { .. }));
+    // cells leaving 0..=255 depend on the cell width, the pointer leaving the tape is an error
+    for source in ["-.", "+<."] {
+        let bytecode = fold(source, 1000);
+        assert!(
+            !matches!(bytecode[0], ByteCode::OutputConst(_)),
+            "{}",
+            source
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,40 @@
+use super::*;
+
+/// Errors reported by [`Compiler`] when the source can't be turned into bytecode
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum CompileError {
+    /// a `[` that is never closed, located in the original source
+    UnmatchedOpenBracket(SourceLocation),
+    /// a `]` with no `[` to close, located in the original source
+    UnmatchedCloseBracket(SourceLocation),
+    /// a character that isn't a command under [`CommentSyntax::Strict`]
+    UnexpectedCharacter(char, SourceLocation),
+}
+
+impl CompileError {
+    pub fn location(&self) -> SourceLocation {
+        match *self {
+            Self::UnmatchedOpenBracket(location)
+            | Self::UnmatchedCloseBracket(location)
+            | Self::UnexpectedCharacter(_, location) => location,
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnmatchedOpenBracket(location) => {
+                write!(f, "unmatched '[' at {}", location)
+            }
+            Self::UnmatchedCloseBracket(location) => {
+                write!(f, "unmatched ']' at {}", location)
+            }
+            Self::UnexpectedCharacter(c, location) => {
+                write!(f, "unexpected character {:?} at {}", c, location)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
@@ -0,0 +1,138 @@
+use super::*;
+
+/// Steps a prelude may take at compile time unless [`CompileOptions`] says otherwise
+pub const DEFAULT_FOLD_BUDGET: u64 = 1 << 20;
+
+/// Furthest right the compiler follows the pointer while folding, keeps a runaway prelude from
+/// building a huge tape at compile time
+const MAX_FOLDED_TAPE_LEN: usize = 1 << 16;
+
+/// Runs the start of a program at compile time on a zeroed tape. It only follows the program
+/// while every cell stays within `0..=255`, where all cell widths and overflow policies agree,
+/// and the pointer stays on the tape
+pub(crate) struct ConstantFolder {
+    tape: Vec<u8>,
+    data_ptr: usize,
+    output: Vec<u8>,
+    steps_left: u64,
+}
+
+impl ConstantFolder {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            tape: Vec::new(),
+            data_ptr: 0,
+            output: Vec::new(),
+            steps_left: budget,
+        }
+    }
+
+    /// runs `node` to completion. Returns `None` when it reads input, leaves the range every
+    /// cell width agrees on or runs out of steps, the folder's state is garbage after that
+    pub fn run(&mut self, node: &Node) -> Option<()> {
+        self.step()?;
+        match node {
+            Node::Op { op, .. } => self.run_op(*op),
+            Node::Loop { body, .. } => {
+                while self.cell(0)? != 0 {
+                    for node in body {
+                        self.run(node)?;
+                    }
+                    self.step()?;
+                }
+                Some(())
+            }
+        }
+    }
+
+    fn run_op(&mut self, op: ByteCode) -> Option<()> {
+        match op {
+            ByteCode::NOP => {}
+            ByteCode::IncrementDataPtr => self.data_ptr = self.index(1)?,
+            ByteCode::DecrementDataPtr => self.data_ptr = self.index(-1)?,
+            ByteCode::MoveDataPtr(amount) => self.data_ptr = self.index(amount)?,
+            ByteCode::IncrementByte => self.add(0, 1)?,
+            ByteCode::DecrementByte => self.add(0, -1)?,
+            ByteCode::AddByte(delta) => self.add(0, delta as i128)?,
+            ByteCode::AddByteAt { offset, delta } => self.add(offset, delta as i128)?,
+            ByteCode::SetByte(value) => self.store(0, value as i128)?,
+            ByteCode::SetByteAt { offset, value } => self.store(offset, value as i128)?,
+            ByteCode::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => {
+                let value = self.cell(source)?;
+                if value != 0 {
+                    self.add(offset, value as i128 * factor as i128)?;
+                }
+            }
+            ByteCode::Scan(stride) => {
+                while self.cell(0)? != 0 {
+                    self.data_ptr = self.index(stride)?;
+                    self.step()?;
+                }
+            }
+            ByteCode::OutputByte => self.output.push(self.cell(0)?),
+            ByteCode::OutputByteAt(offset) => self.output.push(self.cell(offset)?),
+            ByteCode::OutputConst(byte) => self.output.push(byte),
+            ByteCode::InputByte
+            | ByteCode::InputByteAt(_)
+            | ByteCode::LoopOpen { .. }
+            | ByteCode::LoopClose { .. } => return None,
+        }
+        Some(())
+    }
+
+    fn step(&mut self) -> Option<()> {
+        self.steps_left = self.steps_left.checked_sub(1)?;
+        Some(())
+    }
+
+    fn index(&self, offset: isize) -> Option<usize> {
+        let index = self.data_ptr.checked_add_signed(offset)?;
+        (index < MAX_FOLDED_TAPE_LEN).then_some(index)
+    }
+
+    fn cell(&self, offset: isize) -> Option<u8> {
+        let index = self.index(offset)?;
+        Some(self.tape.get(index).copied().unwrap_or(0))
+    }
+
+    fn store(&mut self, offset: isize, value: i128) -> Option<()> {
+        let value = u8::try_from(value).ok()?;
+        let index = self.index(offset)?;
+        if index >= self.tape.len() {
+            self.tape.resize(index + 1, 0);
+        }
+        self.tape[index] = value;
+        Some(())
+    }
+
+    fn add(&mut self, offset: isize, delta: i128) -> Option<()> {
+        let value = self.cell(offset)? as i128 + delta;
+        self.store(offset, value)
+    }
+
+    /// the code that has the same effect as what was folded, starting from a zeroed tape: the
+    /// output it produced, then every non-zero cell it left behind and a move to where it left
+    /// the pointer. All of it is attributed to `span`
+    pub fn into_nodes(self, span: SourceSpan) -> Vec<Node> {
+        let output = self.output.into_iter().map(ByteCode::OutputConst);
+        let cells = self
+            .tape
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, value)| value != 0)
+            .map(|(offset, value)| ByteCode::SetByteAt {
+                offset: offset as isize,
+                value: value as isize,
+            });
+        let pointer = (self.data_ptr != 0).then_some(ByteCode::MoveDataPtr(self.data_ptr as isize));
+        output
+            .chain(cells)
+            .chain(pointer)
+            .map(|op| Node::op(op, span))
+            .collect()
+    }
+}
@@ -0,0 +1,141 @@
+use super::*;
+
+/// A node of the tree the optimization passes work on. Loops own their body instead of
+/// pointing at their partner bracket, jump targets only get worked out once the finished tree
+/// is lowered to [`ByteCode`]
+#[derive(Clone, Debug)]
+pub(crate) enum Node {
+    /// a straight-line instruction, never a `LoopOpen` or `LoopClose`
+    Op { op: ByteCode, span: SourceSpan },
+    /// a loop along with where its `[` and `]` sit in the source
+    Loop {
+        body: Vec<Node>,
+        open: SourceSpan,
+        close: SourceSpan,
+    },
+}
+
+impl Node {
+    pub fn op(op: ByteCode, span: SourceSpan) -> Self {
+        Self::Op { op, span }
+    }
+
+    /// the source the whole node was built from
+    pub fn span(&self) -> SourceSpan {
+        match *self {
+            Self::Op { span, .. } => span,
+            Self::Loop { open, close, .. } => open.merge(close),
+        }
+    }
+
+    /// if the node is a loop whose body is a single instruction, returns that instruction
+    pub fn single_op_loop(&self) -> Option<ByteCode> {
+        match self {
+            Self::Loop { body, .. } => match body[..] {
+                [Self::Op { op, .. }] => Some(op),
+                _ => None,
+            },
+            Self::Op { .. } => None,
+        }
+    }
+
+    /// builds the tree for stripped source, characters that aren't commands are left out.
+    /// Brackets without a partner are reported here
+    pub fn parse(source: &[(char, SourceLocation)]) -> Result<Vec<Node>, CompileError> {
+        //the blocks enclosing the loops that are still open, along with where each `[` is
+        let mut open_loops: Vec<(Vec<Node>, SourceLocation)> = Vec::new();
+        let mut block = Vec::new();
+        for &(c, location) in source {
+            let op = match c {
+                '>' => ByteCode::IncrementDataPtr,
+                '<' => ByteCode::DecrementDataPtr,
+                '+' => ByteCode::IncrementByte,
+                '-' => ByteCode::DecrementByte,
+                ',' => ByteCode::InputByte,
+                '.' => ByteCode::OutputByte,
+                '[' => {
+                    open_loops.push((std::mem::take(&mut block), location));
+                    continue;
+                }
+                ']' => {
+                    let (enclosing, open) = open_loops
+                        .pop()
+                        .ok_or(CompileError::UnmatchedCloseBracket(location))?;
+                    let body = std::mem::replace(&mut block, enclosing);
+                    block.push(Self::Loop {
+                        body,
+                        open: SourceSpan::at(open),
+                        close: SourceSpan::at(location),
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+            block.push(Self::op(op, SourceSpan::at(location)));
+        }
+        match open_loops.pop() {
+            Some((_, location)) => Err(CompileError::UnmatchedOpenBracket(location)),
+            None => Ok(block),
+        }
+    }
+
+    /// how many instructions `nodes` lower to
+    pub fn instruction_count(nodes: &[Node]) -> usize {
+        nodes
+            .iter()
+            .map(|node| match node {
+                Self::Op { .. } => 1,
+                Self::Loop { body, .. } => Self::instruction_count(body) + 2,
+            })
+            .sum()
+    }
+
+    /// flattens the tree into bytecode with every jump pointing at its partner bracket, along
+    /// with the span of every instruction
+    pub fn lower(nodes: &[Node]) -> (Vec<ByteCode>, Vec<SourceSpan>) {
+        let len = Self::instruction_count(nodes);
+        let mut code = Vec::with_capacity(len);
+        let mut spans = Vec::with_capacity(len);
+        Self::lower_into(nodes, &mut code, &mut spans);
+        (code, spans)
+    }
+
+    fn lower_into(nodes: &[Node], code: &mut Vec<ByteCode>, spans: &mut Vec<SourceSpan>) {
+        for node in nodes {
+            match node {
+                Self::Op { op, span } => {
+                    code.push(*op);
+                    spans.push(*span);
+                }
+                Self::Loop { body, open, close } => {
+                    let open_location = code.len();
+                    code.push(ByteCode::LoopOpen { close_location: 0 });
+                    spans.push(*open);
+                    Self::lower_into(body, code, spans);
+                    let close_location = code.len();
+                    code[open_location] = ByteCode::LoopOpen { close_location };
+                    code.push(ByteCode::LoopClose { open_location });
+                    spans.push(*close);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn lowering_resolves_jumps() {
+    let source = "+[>[-]<,]."
+        .chars()
+        .enumerate()
+        .map(|(idx, c)| (c, SourceLocation::new(1, idx + 1)))
+        .collect::<Vec<_>>();
+    let nodes = Node::parse(&source).unwrap();
+    assert_eq!(nodes.len(), 3);
+    let (code, spans) = Node::lower(&nodes);
+    assert_eq!(code.len(), Node::instruction_count(&nodes));
+    assert_eq!(spans.len(), code.len());
+    assert!(matches!(code[1], ByteCode::LoopOpen { close_location: 8 }));
+    assert!(matches!(code[3], ByteCode::LoopOpen { close_location: 5 }));
+    assert!(matches!(code[5], ByteCode::LoopClose { open_location: 3 }));
+    assert!(matches!(code[8], ByteCode::LoopClose { open_location: 1 }));
+}
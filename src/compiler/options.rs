@@ -0,0 +1,215 @@
+use super::*;
+
+/// A single optimization pass, run by [`Compiler::compile_with_options`] in the order
+/// [`CompileOptions`] lists them
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pass {
+    /// folds runs of `+`/`-` and `>`/`<` into a single `AddByte` or `MoveDataPtr`
+    FoldRuns,
+    /// deletes loops reached while the current cell is known to be zero
+    DeadLoops,
+    /// turns clear loops, `[-]` and with wrapping cells `[+]`, into `SetByte`
+    ClearLoops,
+    /// turns multiply loops like `[->++<]` into `MultiplyAdd`
+    MultiplyLoops,
+    /// turns scan loops like `[>]` into `Scan`
+    ScanLoops,
+    /// addresses cells by offset between loops and moves the pointer once
+    PointerOffsets,
+    /// runs the start of the program at compile time, see [`CompileOptions::with_fold_budget`]
+    ConstantFolding,
+    /// drops the code after the last output that only changes the tape, see
+    /// [`Compiler::compile_for_output`]
+    TrailingCode,
+}
+
+impl Pass {
+    pub const ALL: [Pass; 8] = [
+        Pass::FoldRuns,
+        Pass::DeadLoops,
+        Pass::ClearLoops,
+        Pass::MultiplyLoops,
+        Pass::ScanLoops,
+        Pass::PointerOffsets,
+        Pass::ConstantFolding,
+        Pass::TrailingCode,
+    ];
+
+    /// the name the pass goes by on the command line
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::FoldRuns => "fold-runs",
+            Self::DeadLoops => "dead-loops",
+            Self::ClearLoops => "clear-loops",
+            Self::MultiplyLoops => "multiply-loops",
+            Self::ScanLoops => "scan-loops",
+            Self::PointerOffsets => "pointer-offsets",
+            Self::ConstantFolding => "constant-folding",
+            Self::TrailingCode => "trailing-code",
+        }
+    }
+
+    pub(crate) fn run(self, nodes: &mut Vec<Node>, options: &CompileOptions) {
+        match self {
+            Self::FoldRuns => Compiler::optimize_instructions(nodes, options.cell_overflow()),
+            Self::DeadLoops => Compiler::eliminate_dead_loops(nodes),
+            Self::ClearLoops => Compiler::optimize_clear_loops(nodes, options.cell_overflow()),
+            Self::MultiplyLoops => {
+                Compiler::optimize_multiply_loops(nodes, options.cell_overflow())
+            }
+            Self::ScanLoops => Compiler::optimize_scan_loops(nodes),
+            Self::PointerOffsets => Compiler::optimize_pointer_movement(nodes),
+            Self::ConstantFolding => Compiler::fold_constants(nodes, options.fold_budget()),
+            Self::TrailingCode => Compiler::eliminate_trailing_code(nodes),
+        }
+    }
+}
+
+impl fmt::Display for Pass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl std::str::FromStr for Pass {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|pass| pass.name() == name)
+            .ok_or_else(|| format!("unknown pass '{}'", name))
+    }
+}
+
+/// How the compiler treats characters that aren't one of the eight commands
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CommentSyntax {
+    /// every other character is a comment
+    #[default]
+    Standard,
+    /// like `Standard`, but `#` also comments out the rest of its line, commands included
+    HashLineComments,
+    /// only commands and whitespace are allowed, anything else is a
+    /// [`CompileError::UnexpectedCharacter`]
+    Strict,
+}
+
+/// Which optimization passes the compiler runs and in what order. Passes are free to run in
+/// any order but look for the patterns earlier passes leave behind, so `ClearLoops` running
+/// after `PointerOffsets` won't find anything to do
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompileOptions {
+    passes: Vec<Pass>,
+    comment_syntax: CommentSyntax,
+    fold_budget: u64,
+    cell_overflow: CellOverflow,
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        Self::level(2)
+    }
+}
+
+impl CompileOptions {
+    /// the passes for an optimization level:
+    /// - 0 runs nothing, every command becomes its own instruction
+    /// - 1 only folds runs
+    /// - 2 also rewrites loops and addresses cells by offset, what [`Compiler::compile`] does
+    /// - 3 and above also folds the start of the program into constants and trims trailing
+    ///   code, what [`Compiler::compile_for_output`] does
+    pub fn level(level: u8) -> Self {
+        let passes = match level {
+            0 => &Pass::ALL[..0],
+            1 => &Pass::ALL[..1],
+            2 => &Pass::ALL[..6],
+            _ => &Pass::ALL[..],
+        };
+        Self::with_passes(passes.iter().copied())
+    }
+
+    /// runs exactly `passes`, in the order given
+    pub fn with_passes<I: IntoIterator<Item = Pass>>(passes: I) -> Self {
+        Self {
+            passes: passes.into_iter().collect(),
+            comment_syntax: CommentSyntax::default(),
+            fold_budget: DEFAULT_FOLD_BUDGET,
+            cell_overflow: CellOverflow::default(),
+        }
+    }
+
+    /// adds `pass` to the end of the pipeline unless it's already part of it
+    pub fn enable(mut self, pass: Pass) -> Self {
+        if !self.passes.contains(&pass) {
+            self.passes.push(pass);
+        }
+        self
+    }
+
+    /// takes `pass` out of the pipeline
+    pub fn disable(mut self, pass: Pass) -> Self {
+        self.passes.retain(|&enabled| enabled != pass);
+        self
+    }
+
+    pub fn with_comment_syntax(mut self, comment_syntax: CommentSyntax) -> Self {
+        self.comment_syntax = comment_syntax;
+        self
+    }
+
+    pub fn passes(&self) -> &[Pass] {
+        &self.passes
+    }
+
+    pub fn comment_syntax(&self) -> CommentSyntax {
+        self.comment_syntax
+    }
+
+    /// caps how many steps [`Pass::ConstantFolding`] runs the program for at compile time
+    pub fn with_fold_budget(mut self, fold_budget: u64) -> Self {
+        self.fold_budget = fold_budget;
+        self
+    }
+
+    pub fn fold_budget(&self) -> u64 {
+        self.fold_budget
+    }
+
+    /// the overflow policy the program will run with. Passes only merge `+` with `-` (and `>`
+    /// with `<`) when cells wrap, anything else has to see every step to clamp or trap on it
+    pub fn with_cell_overflow(mut self, cell_overflow: CellOverflow) -> Self {
+        self.cell_overflow = cell_overflow;
+        self
+    }
+
+    pub fn cell_overflow(&self) -> CellOverflow {
+        self.cell_overflow
+    }
+}
+
+/// What a single pass did to the code
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PassStatistics {
+    pub pass: Pass,
+    pub instructions_before: usize,
+    pub instructions_after: usize,
+    pub duration: Duration,
+}
+
+impl PassStatistics {
+    /// how many instructions the pass got rid of
+    pub fn removed(&self) -> usize {
+        self.instructions_before
+            .saturating_sub(self.instructions_after)
+    }
+}
+
+/// Everything [`Compiler::compile_with_options`] produces
+#[derive(Clone, Debug)]
+pub struct CompileOutput {
+    pub bytecode: Vec<ByteCode>,
+    pub source_map: SourceMap,
+    /// one entry per pass, in the order they ran
+    pub statistics: Vec<PassStatistics>,
+}
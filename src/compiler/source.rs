@@ -0,0 +1,87 @@
+use super::*;
+
+/// A position in the original (unstripped) source text. Both fields are 1-based.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceLocation {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl SourceLocation {
+    pub fn new(line: usize, column: usize) -> Self {
+        Self { line, column }
+    }
+}
+
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// An inclusive range of the original source, `start` and `end` are the first and last
+/// characters that contributed to an instruction
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct SourceSpan {
+    pub start: SourceLocation,
+    pub end: SourceLocation,
+}
+
+impl SourceSpan {
+    pub fn new(start: SourceLocation, end: SourceLocation) -> Self {
+        Self { start, end }
+    }
+
+    /// a span covering a single character
+    pub fn at(location: SourceLocation) -> Self {
+        Self::new(location, location)
+    }
+
+    /// smallest span covering both `self` and `other`
+    pub fn merge(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.start == self.end {
+            write!(f, "{}:{}", self.start.line, self.start.column)
+        } else {
+            write!(
+                f,
+                "{}:{}-{}:{}",
+                self.start.line, self.start.column, self.end.line, self.end.column
+            )
+        }
+    }
+}
+
+/// Maps instruction indexes of a compiled program back to the source they came from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    spans: Vec<SourceSpan>,
+}
+
+impl SourceMap {
+    pub fn new(spans: Vec<SourceSpan>) -> Self {
+        Self { spans }
+    }
+
+    /// the source range instruction `instruction_index` was compiled from
+    pub fn span(&self, instruction_index: usize) -> Option<SourceSpan> {
+        self.spans.get(instruction_index).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.spans.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = SourceSpan> + '_ {
+        self.spans.iter().copied()
+    }
+}
@@ -1,36 +1,328 @@
 use super::*;
 mod bytecode;
-pub use bytecode::*; 
+mod cell;
+mod error;
+#[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+mod jit;
+mod policy;
+mod tape;
+pub use bytecode::*;
+pub use cell::*;
+pub use error::*;
+pub use policy::*;
+pub use tape::*;
 
-pub struct Interpreter<'inst, 'mem> {
-    memory_buffer: &'mem mut [u8],
+/// Instructions executed between looking at the clock, `Instant::now` is too slow to call on
+/// every step
+const CLOCK_CHECK_INTERVAL: u32 = 4096;
+
+/// How long buffered output may sit before it gets flushed
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// How a call to [`Interpreter::run`] came to an end
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunOutcome {
+    /// the instruction pointer ran off the end of the program
+    Halted,
+    /// the fuel budget ran out, add more with [`Interpreter::add_fuel`] and call
+    /// [`Interpreter::run`] again to carry on where the program stopped
+    OutOfFuel,
+    /// the deadline passed, move it with [`Interpreter::set_deadline`] and call
+    /// [`Interpreter::run`] again to carry on where the program stopped
+    TimedOut,
+}
+
+pub struct Interpreter<'inst, 'mem, 'io, C: Cell = u8> {
+    memory: Tape<'mem, C>,
     instruction_buffer: &'inst mut [ByteCode],
     instruction_ptr: usize,
     data_ptr: usize,
+    input: Box<dyn Read + 'io>,
+    output: Box<dyn Write + 'io>,
+    eof_policy: EofPolicy,
+    cell_overflow: CellOverflow,
+    fuel: Option<u64>,
+    deadline: Option<Instant>,
+    /// turned into `deadline` when the program starts running
+    timeout: Option<Duration>,
+    engine: Engine,
+    /// the machine code for `instruction_buffer`, compiled on the first run with the JIT
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    jit: Option<jit::JitProgram>,
+}
+
+impl<'inst, 'mem, 'io, C: Cell> Default for Interpreter<'inst, 'mem, 'io, C> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
-impl<'inst, 'mem> Interpreter<'inst, 'mem> {
+impl<'inst, 'mem, 'io, C: Cell> Interpreter<'inst, 'mem, 'io, C> {
+    /// creates an interpreter that reads from stdin, writes to a buffered stdout and runs on a
+    /// tape that grows to the right
     pub fn new() -> Self {
         Self {
-            memory_buffer: &mut [],
+            memory: Tape::growable(TapeGrowth::Right, DEFAULT_MAX_TAPE_LEN),
             instruction_buffer: &mut [],
             instruction_ptr: 0,
             data_ptr: 0,
+            input: Box::new(io::stdin()),
+            output: Box::new(BufWriter::new(io::stdout())),
+            eof_policy: EofPolicy::default(),
+            cell_overflow: CellOverflow::default(),
+            fuel: None,
+            deadline: None,
+            timeout: None,
+            engine: Engine::default(),
+            #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+            jit: None,
         }
     }
 
-    pub fn with_memory(mut self, buffer: &'mem mut [u8]) -> Self {
-        self.memory_buffer = buffer;
+    /// sets a fixed size tape, its element type picks the cell width the program runs with.
+    /// Optimized code assumes it starts on a zeroed tape, run code from
+    /// [`Compiler::compile_unoptimized`] on a buffer that already holds data
+    pub fn with_memory(mut self, buffer: &'mem mut [C]) -> Self {
+        self.memory = Tape::fixed(buffer, self.memory.max_len());
+        self
+    }
+
+    /// gives the interpreter its own tape that grows on demand in the directions of `growth`
+    pub fn with_growable_memory(mut self, growth: TapeGrowth) -> Self {
+        self.memory = Tape::growable(growth, self.memory.max_len());
+        self
+    }
+
+    /// caps the number of cells a growable tape may grow to, defaults to
+    /// [`DEFAULT_MAX_TAPE_LEN`]
+    pub fn with_max_memory(mut self, max_len: usize) -> Self {
+        self.memory.set_max_len(max_len);
         self
     }
 
+    /// limits how many instructions `run` may execute before it stops with
+    /// [`RunOutcome::OutOfFuel`]. Every [`ByteCode`] counts as one, however many source commands
+    /// it was fused from
+    pub fn with_fuel(mut self, fuel: u64) -> Self {
+        self.fuel = Some(fuel);
+        self
+    }
+
+    /// makes `run` stop with [`RunOutcome::TimedOut`] once `deadline` has passed
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self.timeout = None;
+        self
+    }
+
+    /// makes `run` stop with [`RunOutcome::TimedOut`] once `timeout` has elapsed. The clock
+    /// starts with the first call to `run`, not here
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self.deadline = None;
+        self
+    }
+
+    /// the fuel left over, `None` if there is no limit
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// tops up the fuel budget of an interpreter that was built with [`Interpreter::with_fuel`]
+    /// and starts metering one that wasn't
+    pub fn add_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(self.fuel.unwrap_or(0).saturating_add(fuel));
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+        self.timeout = None;
+    }
+
+    /// the current contents of the tape
+    pub fn memory(&self) -> &[C] {
+        self.memory.cells()
+    }
+
     pub fn with_instruction_buffer(mut self, buffer: &'inst mut [ByteCode]) -> Self {
         self.instruction_buffer = buffer;
+        #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+        {
+            self.jit = None;
+        }
         self
     }
 
-    fn data(&self) -> u8 {
-        unsafe { *self.memory_buffer.get_unchecked(self.data_ptr) }
+    /// picks how the program gets executed, see [`Engine`]. Both engines produce the same
+    /// output, errors and fuel accounting, the JIT only checks the deadline less often
+    pub fn with_engine(mut self, engine: Engine) -> Self {
+        self.engine = engine;
+        self
+    }
+
+    /// sets where `,` reads its bytes from
+    pub fn with_input<R: Read + 'io>(mut self, input: R) -> Self {
+        self.input = Box::new(input);
+        self
+    }
+
+    /// sets where `.` writes its bytes to. The handle is used as-is, wrap it in a
+    /// [`BufWriter`] if every byte hitting it directly is too expensive
+    pub fn with_output<W: Write + 'io>(mut self, output: W) -> Self {
+        self.output = Box::new(output);
+        self
+    }
+
+    /// sets what `,` stores once the input is exhausted
+    pub fn with_eof_policy(mut self, eof_policy: EofPolicy) -> Self {
+        self.eof_policy = eof_policy;
+        self
+    }
+
+    /// sets how `+` and `-` behave at the edges of a cell's range
+    pub fn with_cell_overflow(mut self, cell_overflow: CellOverflow) -> Self {
+        self.cell_overflow = cell_overflow;
+        self
+    }
+
+    fn data(&self) -> C {
+        // SAFETY: `run` checks `data_ptr` against the tape before executing anything and
+        // `move_data_ptr` refuses to leave it, so `data_ptr` is always in bounds here
+        unsafe { *self.memory.cells().get_unchecked(self.data_ptr) }
+    }
+
+    fn add_data(&mut self, delta: isize) -> Result<(), RuntimeError> {
+        let value = self.cell_overflow.apply(self.data(), delta as i128);
+        self.store_data(value)
+    }
+
+    fn set_data(&mut self, value: isize) -> Result<(), RuntimeError> {
+        let value = self.cell_overflow.apply(C::ZERO, value as i128);
+        self.store_data(value)
+    }
+
+    fn store_data(&mut self, value: Option<C>) -> Result<(), RuntimeError> {
+        self.store_cell(self.data_ptr, value)
+    }
+
+    fn add_data_at(&mut self, offset: isize, delta: isize) -> Result<(), RuntimeError> {
+        let index = self.cell_index(offset)?;
+        let value = self
+            .cell_overflow
+            .apply(self.memory.cells()[index], delta as i128);
+        self.store_cell(index, value)
+    }
+
+    fn set_data_at(&mut self, offset: isize, value: isize) -> Result<(), RuntimeError> {
+        let index = self.cell_index(offset)?;
+        let value = self.cell_overflow.apply(C::ZERO, value as i128);
+        self.store_cell(index, value)
+    }
+
+    /// adds `factor` times the cell `source` away to the cell `offset` away, leaving both alone
+    /// when the source cell is zero like the loop this was compiled from would
+    fn multiply_add(
+        &mut self,
+        source: isize,
+        offset: isize,
+        factor: isize,
+    ) -> Result<(), RuntimeError> {
+        let source = self.cell_index(source)?;
+        let value = self.memory.cells()[source];
+        if value == C::ZERO {
+            return Ok(());
+        }
+        let index = self.cell_index(offset)?;
+        let target = self.memory.cells()[index];
+        let result = self
+            .cell_overflow
+            .apply(target, value.to_i128() * factor as i128);
+        self.store_cell(index, result)
+    }
+
+    fn store_cell(&mut self, index: usize, value: Option<C>) -> Result<(), RuntimeError> {
+        match value {
+            Some(value) => {
+                self.memory.cells_mut()[index] = value;
+                Ok(())
+            }
+            None => Err(RuntimeError::CellOverflow {
+                instruction: self.instruction_ptr,
+                data_ptr: self.memory.position(index as isize),
+            }),
+        }
+    }
+
+    /// moves the data pointer by `offset` cells, growing the tape if it's allowed to and
+    /// failing instead of leaving it otherwise
+    fn move_data_ptr(&mut self, offset: isize) -> Result<(), RuntimeError> {
+        self.data_ptr = self.cell_index(offset)?;
+        Ok(())
+    }
+
+    /// moves the data pointer `stride` cells at a time until it finds a zero cell
+    fn scan(&mut self, stride: isize) -> Result<(), RuntimeError> {
+        let cells = self.memory.cells();
+        let found = match stride {
+            1 => find_zero(&cells[self.data_ptr..]).map(|idx| self.data_ptr + idx),
+            -1 => (0..=self.data_ptr).rev().find(|&idx| cells[idx] == C::ZERO),
+            _ if stride > 0 => (self.data_ptr..cells.len())
+                .step_by(stride as usize)
+                .find(|&idx| cells[idx] == C::ZERO),
+            _ => (0..=self.data_ptr)
+                .rev()
+                .step_by(stride.unsigned_abs())
+                .find(|&idx| cells[idx] == C::ZERO),
+        };
+        match found {
+            Some(idx) => {
+                self.data_ptr = idx;
+                Ok(())
+            }
+            None => {
+                //every stop left on the tape is non-zero, the first stop past its edge is where
+                //the scan ends because cells the tape grows into start out zeroed
+                let step = stride.unsigned_abs();
+                let offset = if stride > 0 {
+                    let stops = (cells.len() - 1 - self.data_ptr) / step + 1;
+                    (stops * step) as isize
+                } else {
+                    let stops = self.data_ptr / step + 1;
+                    -((stops * step) as isize)
+                };
+                self.move_data_ptr(offset)
+            }
+        }
+    }
+
+    /// the storage index of the cell `offset` away from the data pointer. Grows the tape when
+    /// the cell isn't on it yet, which shifts the data pointer along if it grew to the left
+    fn cell_index(&mut self, offset: isize) -> Result<usize, RuntimeError> {
+        let index = self.data_ptr as isize + offset;
+        if index >= 0 && (index as usize) < self.memory.len() {
+            return Ok(index as usize);
+        }
+        let shift = self.grow_memory_to_fit(index)?;
+        self.data_ptr += shift;
+        Ok((index + shift as isize) as usize)
+    }
+
+    /// makes room on the tape for storage index `index`, see [`Tape::grow_to_fit`]
+    fn grow_memory_to_fit(&mut self, index: isize) -> Result<usize, RuntimeError> {
+        self.memory.grow_to_fit(index).map_err(|limit| match limit {
+            TapeLimit::Edge => self.pointer_out_of_bounds(index),
+            TapeLimit::MaxLen(max_len) => RuntimeError::TapeLimitExceeded {
+                instruction: self.instruction_ptr,
+                max_len,
+            },
+        })
+    }
+
+    fn pointer_out_of_bounds(&self, index: isize) -> RuntimeError {
+        RuntimeError::PointerOutOfBounds {
+            instruction: self.instruction_ptr,
+            data_ptr: self.memory.position(index),
+        }
     }
 
     fn current_instruction(&self) -> ByteCode {
@@ -41,24 +333,142 @@ impl<'inst, 'mem> Interpreter<'inst, 'mem> {
         self.instruction_ptr < self.instruction_buffer.len()
     }
 
-    pub fn run(&mut self) {
-        let mut stdout = BufWriter::new(io::stdout());
-        let mut t0 = Instant::now();
+    /// runs the program until it halts, fails or uses up its fuel or time. Stopping early leaves
+    /// the interpreter where it was so calling `run` again resumes the program
+    pub fn run(&mut self) -> Result<RunOutcome, RuntimeError> {
+        if self.data_ptr >= self.memory.len() {
+            return Err(self.pointer_out_of_bounds(self.data_ptr as isize));
+        }
+        if let Some(timeout) = self.timeout.take() {
+            self.deadline = Some(Instant::now() + timeout);
+        }
+        let outcome = self.execute();
+        //don't lose whatever the program printed, even if it failed
+        let flushed = self.output.flush();
+        let outcome = outcome?;
+        flushed?;
+        Ok(outcome)
+    }
+
+    #[cfg(all(target_arch = "x86_64", target_os = "linux"))]
+    fn execute(&mut self) -> Result<RunOutcome, RuntimeError> {
+        match (self.engine, self.cell_overflow) {
+            (Engine::Jit, CellOverflow::Wrapping) => self.execute_jit(),
+            _ => self.execute_instructions(),
+        }
+    }
+
+    #[cfg(not(all(target_arch = "x86_64", target_os = "linux")))]
+    fn execute(&mut self) -> Result<RunOutcome, RuntimeError> {
+        self.execute_instructions()
+    }
+
+    /// executes the instruction under the instruction pointer, `false` if there's no fuel
+    /// left for it
+    fn step(&mut self) -> Result<bool, RuntimeError> {
+        if let Some(fuel) = self.fuel {
+            if fuel == 0 {
+                return Ok(false);
+            }
+            self.fuel = Some(fuel - 1);
+        }
+        self.current_instruction().execute(self)?;
+        Ok(true)
+    }
+
+    fn execute_instructions(&mut self) -> Result<RunOutcome, RuntimeError> {
+        let mut last_flush = Instant::now();
+        let mut until_clock_check = CLOCK_CHECK_INTERVAL;
         while self.instruction_pointer_in_bounds() {
-            self.current_instruction().execute(self, &mut stdout);
-            if t0.elapsed().as_millis() > 200 {
-                t0 = Instant::now();
-                stdout.flush().unwrap();
+            if !self.step()? {
+                return Ok(RunOutcome::OutOfFuel);
+            }
+
+            until_clock_check -= 1;
+            if until_clock_check == 0 {
+                until_clock_check = CLOCK_CHECK_INTERVAL;
+                let now = Instant::now();
+                if self.deadline.is_some_and(|deadline| now >= deadline) {
+                    return Ok(RunOutcome::TimedOut);
+                }
+                if now - last_flush > FLUSH_INTERVAL {
+                    last_flush = now;
+                    self.output.flush()?;
+                }
+            }
+        }
+        Ok(RunOutcome::Halted)
+    }
+
+    /// writes the cell `offset` away from the data pointer as a byte
+    fn output_byte(&mut self, offset: isize) -> Result<(), RuntimeError> {
+        let index = self.cell_index(offset)?;
+        let output = &[self.memory.cells()[index].to_byte()][..];
+        self.output.write_all(output)?;
+        Ok(())
+    }
+
+    /// reads a byte into the cell `offset` away, falling back on the [`EofPolicy`] once input
+    /// runs out. Output is flushed first so a prompt shows up before the read blocks
+    fn input_byte(&mut self, offset: isize) -> Result<(), RuntimeError> {
+        let index = self.cell_index(offset)?;
+        self.output.flush()?;
+        let input_byte = match self.read_byte()? {
+            Some(input_byte) => C::from_byte(input_byte),
+            None => match self.eof_policy {
+                EofPolicy::Zero => C::ZERO,
+                EofPolicy::Max => C::MAX,
+                EofPolicy::Unchanged => self.memory.cells()[index],
+                EofPolicy::Error => {
+                    return Err(RuntimeError::UnexpectedEof {
+                        instruction: self.instruction_ptr,
+                    })
+                }
+            },
+        };
+        self.memory.cells_mut()[index] = input_byte;
+        Ok(())
+    }
+
+    /// reads a single byte from the input, `None` means the input is exhausted
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut input_byte = [0u8];
+        loop {
+            match self.input.read(&mut input_byte) {
+                Ok(0) => return Ok(None),
+                Ok(_) => return Ok(Some(input_byte[0])),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
             }
         }
-        stdout.flush().unwrap();
     }
 }
 
+/// index of the first zero cell in `cells`. Checks a whole chunk of cells at a time so the
+/// comparisons get vectorized, the same trick `memchr` uses
+fn find_zero<C: Cell>(cells: &[C]) -> Option<usize> {
+    const CHUNK_LEN: usize = 32;
+    let mut chunks = cells.chunks_exact(CHUNK_LEN);
+    let mut chunk_start = 0;
+    for chunk in chunks.by_ref() {
+        let has_zero = chunk
+            .iter()
+            .fold(false, |has_zero, &cell| has_zero | (cell == C::ZERO));
+        if has_zero {
+            break;
+        }
+        chunk_start += CHUNK_LEN;
+    }
+    cells[chunk_start..]
+        .iter()
+        .position(|&cell| cell == C::ZERO)
+        .map(|idx| chunk_start + idx)
+}
+
 #[test]
 fn simple_optimization_test() {
     let source = "++[--]++";
-    let bytecode = Compiler::compile(source);
+    let bytecode = Compiler::compile(source).unwrap();
     println!("{:?}", bytecode);
 }
 
@@ -79,25 +489,486 @@ fn two_plus_five() {
     ]
     < .        #Print out c0 which has the value 55 which translates to 
     ";
-    let mut bytecode = Compiler::compile(source);
+    let mut bytecode = Compiler::compile(source).unwrap();
     let mut memory = vec![0u8; 32];
+    let mut output = Vec::new();
     Interpreter::new()
         .with_instruction_buffer(&mut bytecode)
         .with_memory(&mut memory)
-        .run();
+        .with_output(&mut output)
+        .run()
+        .unwrap();
+    assert_eq!(output, b"7");
 }
 
 #[test]
 fn hello_world() {
     let source = r"++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.--------.>>+.>++.";
-    let mut bytecode = Compiler::compile(source);
+    let mut bytecode = Compiler::compile(source).unwrap();
     // println!("optimized code = {:?}", tokens);
 
     let mut memory = vec![0u8; 1024];
+    let mut output = Vec::new();
+    Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_output(&mut output)
+        .run()
+        .unwrap();
+    assert_eq!(output, b"Hello World!\n");
+}
+
+#[test]
+fn reads_from_supplied_input() {
+    let mut bytecode = Compiler::compile(",+.>,+.").unwrap();
+    let mut memory = vec![0u8; 2];
+    let mut output = Vec::new();
     Interpreter::new()
         .with_instruction_buffer(&mut bytecode)
         .with_memory(&mut memory)
+        .with_input(&b"HAL"[..])
+        .with_output(&mut output)
+        .run()
+        .unwrap();
+    assert_eq!(output, b"IB");
+}
+
+#[test]
+fn output_is_flushed_before_reading() {
+    /// remembers how much had been written each time it was flushed
+    #[derive(Default)]
+    struct FlushLog {
+        written: usize,
+        flushes: Vec<usize>,
+    }
+    impl Write for FlushLog {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written += buf.len();
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            self.flushes.push(self.written);
+            Ok(())
+        }
+    }
+
+    for engine in [Engine::Interpreter, Engine::Jit] {
+        let mut bytecode = Compiler::compile(".,.,.").unwrap();
+        let mut output = FlushLog::default();
+        Interpreter::<u8>::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_engine(engine)
+            .with_input(&b"ab"[..])
+            .with_output(&mut output)
+            .run()
+            .unwrap();
+        assert!(
+            output.flushes.starts_with(&[1, 2]),
+            "{:?} flushed after {:?} bytes",
+            engine,
+            output.flushes
+        );
+    }
+}
+
+#[test]
+fn eof_policies() {
+    let run_with_policy = |eof_policy| {
+        let mut bytecode = Compiler::compile("+++++,.").unwrap();
+        let mut memory = vec![0u8; 1];
+        let mut output = Vec::new();
+        let result = Interpreter::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_memory(&mut memory)
+            .with_input(io::empty())
+            .with_output(&mut output)
+            .with_eof_policy(eof_policy)
+            .run();
+        result.map(|_| output)
+    };
+    assert_eq!(run_with_policy(EofPolicy::Zero).unwrap(), [0]);
+    assert_eq!(run_with_policy(EofPolicy::Max).unwrap(), [255]);
+    assert_eq!(run_with_policy(EofPolicy::Unchanged).unwrap(), [5]);
+    assert!(matches!(
+        run_with_policy(EofPolicy::Error),
+        Err(RuntimeError::UnexpectedEof { instruction: 1 })
+    ));
+}
+
+#[test]
+fn pointer_out_of_bounds_is_an_error() {
+    let run = |source| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut memory = vec![0u8; 4];
+        Interpreter::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_memory(&mut memory)
+            .run()
+    };
+    assert!(matches!(run(">>>+<<<"), Ok(RunOutcome::Halted)));
+    assert!(matches!(
+        run("+<"),
+        Err(RuntimeError::PointerOutOfBounds {
+            instruction: 1,
+            data_ptr: -1
+        })
+    ));
+    assert!(matches!(
+        run("+[>+]"),
+        Err(RuntimeError::PointerOutOfBounds {
+            instruction: 2,
+            data_ptr: 4
+        })
+    ));
+}
+
+#[test]
+fn cell_overflow_policies() {
+    let run_with_policy = |source, cell_overflow| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut memory = vec![0u8; 1];
+        let result = Interpreter::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_memory(&mut memory)
+            .with_cell_overflow(cell_overflow)
+            .run();
+        result.map(|_| memory[0])
+    };
+    let plenty = "+".repeat(300);
+    assert_eq!(run_with_policy("-", CellOverflow::Wrapping).unwrap(), 255);
+    assert_eq!(
+        run_with_policy(&plenty, CellOverflow::Wrapping).unwrap(),
+        44
+    );
+    assert_eq!(run_with_policy("-", CellOverflow::Saturating).unwrap(), 0);
+    assert_eq!(
+        run_with_policy(&plenty, CellOverflow::Saturating).unwrap(),
+        255
+    );
+    assert_eq!(run_with_policy("++--", CellOverflow::Trap).unwrap(), 0);
+    assert!(matches!(
+        run_with_policy("+--", CellOverflow::Trap),
+        Err(RuntimeError::CellOverflow {
+            instruction: 0,
+            data_ptr: 0
+        })
+    ));
+    assert!(matches!(
+        run_with_policy(&plenty, CellOverflow::Trap),
+        Err(RuntimeError::CellOverflow { .. })
+    ));
+
+    //positions count from the starting cell even after the tape grew to the left
+    let mut bytecode = Compiler::compile("<-").unwrap();
+    let result = Interpreter::<u8>::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_growable_memory(TapeGrowth::Both)
+        .with_cell_overflow(CellOverflow::Trap)
         .run();
+    assert!(matches!(
+        result,
+        Err(RuntimeError::CellOverflow { data_ptr: -1, .. })
+    ));
 }
 
+#[test]
+fn wide_cells() {
+    let mut bytecode = Compiler::compile(&format!("{}.>-.>,.", "+".repeat(256 + 65))).unwrap();
+    let mut memory = vec![0u16; 3];
+    let mut output = Vec::new();
+    Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_input(io::empty())
+        .with_output(&mut output)
+        .with_eof_policy(EofPolicy::Max)
+        .run()
+        .unwrap();
+    assert_eq!(memory, [321, u16::MAX, u16::MAX]);
+    assert_eq!(output, [b'A', 0xff, 0xff]);
 
+    let mut bytecode = Compiler::compile("-").unwrap();
+    let mut memory = vec![0u64; 1];
+    Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .run()
+        .unwrap();
+    assert_eq!(memory, [u64::MAX]);
+}
+
+#[test]
+fn growable_memory() {
+    let run = |source, growth, max_len| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut interpreter = Interpreter::<u8>::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_growable_memory(growth)
+            .with_max_memory(max_len);
+        let result = interpreter.run();
+        result.map(|_| interpreter.memory().to_vec())
+    };
+    let far_right = format!("{}+", ">".repeat(5000));
+    let memory = run(&far_right, TapeGrowth::Right, DEFAULT_MAX_TAPE_LEN).unwrap();
+    assert_eq!(memory[5000], 1);
+
+    let memory = run("+<<+", TapeGrowth::Both, DEFAULT_MAX_TAPE_LEN).unwrap();
+    let touched = (0..memory.len())
+        .filter(|&idx| memory[idx] == 1)
+        .collect::<Vec<_>>();
+    assert_eq!(touched.len(), 2);
+    assert_eq!(touched[1] - touched[0], 2);
+
+    assert!(matches!(
+        run("+<<+", TapeGrowth::Right, 4),
+        Err(RuntimeError::PointerOutOfBounds {
+            instruction: 1,
+            data_ptr: -2
+        })
+    ));
+    assert!(matches!(
+        run(&far_right, TapeGrowth::Right, 4096),
+        Err(RuntimeError::TapeLimitExceeded {
+            instruction: 0,
+            max_len: 4096
+        })
+    ));
+}
+
+#[test]
+fn fuel_runs_out_and_resumes() {
+    let mut bytecode = Compiler::compile_unoptimized("+++.").unwrap();
+    let mut memory = vec![0u8; 1];
+    let mut output = Vec::new();
+    let mut interpreter = Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_output(&mut output)
+        .with_fuel(2);
+    assert_eq!(interpreter.run().unwrap(), RunOutcome::OutOfFuel);
+    assert_eq!(interpreter.memory(), [2]);
+    assert_eq!(interpreter.fuel(), Some(0));
+
+    interpreter.add_fuel(10);
+    assert_eq!(interpreter.run().unwrap(), RunOutcome::Halted);
+    assert_eq!(interpreter.fuel(), Some(8));
+    drop(interpreter);
+    assert_eq!(output, [3]);
+}
+
+#[test]
+fn loop_at_the_first_instruction_repeats() {
+    let mut bytecode = Compiler::compile_unoptimized("[-]").unwrap();
+    let mut memory = [3u8];
+    let outcome = Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .run()
+        .unwrap();
+    assert_eq!(outcome, RunOutcome::Halted);
+    assert_eq!(memory, [0]);
+}
+
+#[test]
+fn infinite_loop_times_out() {
+    let mut bytecode = Compiler::compile("+[]").unwrap();
+    let mut memory = vec![0u8; 1];
+    let mut interpreter = Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_timeout(Duration::from_millis(10));
+    assert_eq!(interpreter.run().unwrap(), RunOutcome::TimedOut);
+
+    interpreter.set_deadline(None);
+    interpreter.add_fuel(1000);
+    assert_eq!(interpreter.run().unwrap(), RunOutcome::OutOfFuel);
+}
+
+#[test]
+fn timeout_starts_with_the_run() {
+    let mut bytecode = Compiler::compile("+[]").unwrap();
+    let mut memory = vec![0u8; 1];
+    let mut interpreter = Interpreter::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_memory(&mut memory)
+        .with_timeout(Duration::from_millis(200))
+        .with_fuel(100_000);
+    std::thread::sleep(Duration::from_millis(250));
+    assert_eq!(interpreter.run().unwrap(), RunOutcome::OutOfFuel);
+}
+
+/// runs `source` on a tape of `tape_len` cells, or one that grows both ways for `None`, and
+/// checks the optimized program leaves the same tape and output as the unoptimized one. Every
+/// pass but `TrailingCode` runs, that one is free to drop work that never gets printed
+#[cfg(test)]
+fn run_against_unoptimized<C: Cell>(
+    source: &str,
+    tape_len: Option<usize>,
+    input: &[u8],
+) -> (Vec<C>, Vec<u8>) {
+    let run = |mut bytecode: Vec<ByteCode>| {
+        let mut memory = vec![C::ZERO; tape_len.unwrap_or(0)];
+        let mut output = Vec::new();
+        let interpreter = Interpreter::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_input(input)
+            .with_output(&mut output);
+        let mut interpreter = match tape_len {
+            Some(_) => interpreter.with_memory(&mut memory),
+            None => interpreter.with_growable_memory(TapeGrowth::Both),
+        };
+        interpreter.run().unwrap();
+        let memory = interpreter.memory().to_vec();
+        drop(interpreter);
+        (memory, output)
+    };
+    let options = CompileOptions::level(3).disable(Pass::TrailingCode);
+    let bytecode = Compiler::compile_with_options(source, &options)
+        .unwrap()
+        .bytecode;
+    let optimized = run(bytecode);
+    assert_eq!(
+        optimized,
+        run(Compiler::compile_unoptimized(source).unwrap()),
+        "{}",
+        source
+    );
+    optimized
+}
+
+#[test]
+fn clear_loops_match_unoptimized_code() {
+    let (memory, _) = run_against_unoptimized::<u16>("+++++[-]++>---[+]-->[-]-", Some(3), b"");
+    assert_eq!(memory, [2, u16::MAX - 1, u16::MAX]);
+}
+
+#[test]
+fn clear_loops_respect_the_overflow_policy() {
+    let source = ",[+].";
+    for cell_overflow in [CellOverflow::Saturating, CellOverflow::Trap] {
+        let run = |mut bytecode: Vec<ByteCode>| {
+            let mut memory = [0u8; 1];
+            let mut output = Vec::new();
+            let outcome = Interpreter::new()
+                .with_instruction_buffer(&mut bytecode)
+                .with_memory(&mut memory)
+                .with_cell_overflow(cell_overflow)
+                .with_input(&[200][..])
+                .with_output(&mut output)
+                .with_fuel(10_000)
+                .run()
+                .map_err(|err| std::mem::discriminant(&err));
+            (outcome, output)
+        };
+        let options = CompileOptions::level(2).with_cell_overflow(cell_overflow);
+        let optimized = run(Compiler::compile_with_options(source, &options)
+            .unwrap()
+            .bytecode);
+        assert_eq!(
+            optimized,
+            run(Compiler::compile_unoptimized(source).unwrap())
+        );
+        assert!(optimized.1.is_empty());
+    }
+}
+
+#[test]
+fn overflow_policies_survive_optimization() {
+    let programs = [
+        ",+-.",
+        ",-+.",
+        "-+.",
+        ",,[->+-<]>.",
+        ",>++[-<+->]<.",
+        "+++[->-+<]>.",
+    ];
+    for cell_overflow in [CellOverflow::Saturating, CellOverflow::Trap] {
+        let run = |source, level| {
+            let options = CompileOptions::level(level).with_cell_overflow(cell_overflow);
+            let mut bytecode = Compiler::compile_with_options(source, &options)
+                .unwrap()
+                .bytecode;
+            let mut memory = [0u8; 4];
+            let mut output = Vec::new();
+            let outcome = Interpreter::new()
+                .with_instruction_buffer(&mut bytecode)
+                .with_memory(&mut memory)
+                .with_cell_overflow(cell_overflow)
+                .with_input(&[255, 255][..])
+                .with_output(&mut output)
+                .run()
+                .map_err(|err| std::mem::discriminant(&err));
+            (outcome, output)
+        };
+        for source in programs {
+            assert_eq!(
+                run(source, 2),
+                run(source, 0),
+                "{} with {:?}",
+                source,
+                cell_overflow
+            );
+        }
+    }
+}
+
+#[test]
+fn multiply_loops_match_unoptimized_code() {
+    let source = "+++++[->++>>+++<<<]>>>>+++[-<<-<+>>>]<<<[->>>>>+<<<<<]";
+    let (memory, _) = run_against_unoptimized::<u8>(source, Some(8), b"");
+    assert_eq!(memory, [0, 0, 253, 15, 0, 0, 13, 0]);
+}
+
+#[test]
+fn scan_loops_match_unoptimized_code() {
+    let mut setup = String::from(">+>+>+>>+>+>>");
+    setup.push_str(&"+>".repeat(100));
+    let source = format!("{}<[<]<[<<<]>+>[>>]", setup);
+    run_against_unoptimized::<u32>(&source, Some(256), b"");
+}
+
+#[test]
+fn scan_past_the_end_of_the_tape() {
+    let run = |source, memory: &mut [u8]| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        Interpreter::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_memory(memory)
+            .run()
+    };
+    assert!(matches!(
+        run("+>+>+>+<<<[>>]", &mut [0; 5]),
+        Ok(RunOutcome::Halted)
+    ));
+    assert!(matches!(
+        run("+>+>+>+<<<[>>]", &mut [0; 4]),
+        Err(RuntimeError::PointerOutOfBounds { data_ptr: 4, .. })
+    ));
+    assert!(matches!(
+        run("+>+>+>+>+[<<<]", &mut [0; 5]),
+        Err(RuntimeError::PointerOutOfBounds { data_ptr: -2, .. })
+    ));
+
+    let mut bytecode = Compiler::compile("+>+>+<<[>>>]+").unwrap();
+    let mut interpreter = Interpreter::<u8>::new()
+        .with_instruction_buffer(&mut bytecode)
+        .with_growable_memory(TapeGrowth::Both)
+        .with_max_memory(4);
+    interpreter.run().unwrap();
+    assert_eq!(interpreter.memory(), [1, 1, 1, 1]);
+}
+
+#[test]
+fn offset_addressing_matches_unoptimized_code() {
+    let source = ">+++[>++<-]>[<+>>+++<-]<<+>>>,.<.<<[>>+>>+<<<<-]";
+    run_against_unoptimized::<u8>(source, None, b"x");
+}
+
+#[test]
+fn folded_constants_match_unoptimized_code() {
+    let source = ">++++++++[<+++++++++>-]<.>++++[<+++++++>-]<+.+++++++..+++.[-]>>,.";
+    let folded = Compiler::compile_with_options(source, &CompileOptions::level(3)).unwrap();
+    assert!(matches!(folded.bytecode[0], ByteCode::OutputConst(b'H')));
+    let (_, output) = run_against_unoptimized::<u8>(source, None, b"!");
+    assert_eq!(output, b"Hello!");
+}
@@ -3,18 +3,55 @@ use super::*;
 #[derive(Copy, Clone, Debug)]
 pub enum ByteCode {
     NOP,
-    QuickIncrementDataPtr(usize),
-    QuickDecrementDataPtr(usize),
-    QuickIncrementByte(usize),
-    QuickDecrementByte(usize),
+    /// moves the data pointer by a signed distance, emitted for runs of `>` and `<`
+    MoveDataPtr(isize),
+    /// adds a signed amount to the current cell, emitted for runs of `+` and `-`
+    AddByte(isize),
+    /// stores a constant in the current cell, emitted for clear loops like `[-]+++`. The value
+    /// is added to a zeroed cell under the interpreter's [`CellOverflow`] policy, so `[-]-`
+    /// stores the cell's maximum when wrapping
+    SetByte(isize),
+    /// adds `factor` times the cell `source` away from the data pointer to the cell `offset`
+    /// away from it, emitted for multiply loops like `[->+>++<<]`. Does nothing when the source
+    /// cell is zero
+    MultiplyAdd {
+        source: isize,
+        offset: isize,
+        factor: isize,
+    },
+    /// [`ByteCode::AddByte`] for the cell `offset` away from the data pointer, without moving
+    /// it. The compiler rewrites the instructions between loops into these followed by a single
+    /// pointer move
+    AddByteAt {
+        offset: isize,
+        delta: isize,
+    },
+    /// [`ByteCode::SetByte`] for the cell `offset` away from the data pointer
+    SetByteAt {
+        offset: isize,
+        value: isize,
+    },
+    /// writes the cell `offset` away from the data pointer
+    OutputByteAt(isize),
+    /// writes a byte the compiler worked out ahead of time
+    OutputConst(u8),
+    /// reads into the cell `offset` away from the data pointer
+    InputByteAt(isize),
+    /// moves the data pointer `stride` cells at a time until it lands on a zero cell, emitted for
+    /// scan loops like `[>]` and `[<<<]`
+    Scan(isize),
     IncrementDataPtr,
     DecrementDataPtr,
     IncrementByte,
     DecrementByte,
     OutputByte,
     InputByte,
-    LoopOpen { close_location: usize },
-    LoopClose { open_location: usize },
+    LoopOpen {
+        close_location: usize,
+    },
+    LoopClose {
+        open_location: usize,
+    },
 }
 
 impl ByteCode {
@@ -26,69 +63,92 @@ impl ByteCode {
         };
         Some(loc)
     }
-    pub fn execute<'a, 'b, OUT: Write>(self, state: &mut Interpreter<'a, 'b>, mut stdout: OUT) {
+    pub fn execute<C: Cell>(self, state: &mut Interpreter<C>) -> Result<(), RuntimeError> {
         match self {
             Self::IncrementDataPtr => {
-                state.data_ptr += 1;
+                state.move_data_ptr(1)?;
             }
 
-            Self::QuickIncrementDataPtr(ofx) => {
-                state.data_ptr += ofx;
+            Self::MoveDataPtr(ofx) => {
+                state.move_data_ptr(ofx)?;
             }
 
             Self::DecrementDataPtr => {
-                state.data_ptr -= 1;
-            }
-
-            Self::QuickDecrementDataPtr(ofx) => {
-                state.data_ptr -= ofx;
+                state.move_data_ptr(-1)?;
             }
 
             Self::IncrementByte => {
-                state.memory_buffer[state.data_ptr] += 1;
+                state.add_data(1)?;
             }
 
-            Self::QuickIncrementByte(ofx) => {
-                state.memory_buffer[state.data_ptr] += ofx as u8;
+            Self::AddByte(ofx) => {
+                state.add_data(ofx)?;
             }
 
             Self::DecrementByte => {
-                state.memory_buffer[state.data_ptr] -= 1;
+                state.add_data(-1)?;
+            }
+
+            Self::SetByte(value) => {
+                state.set_data(value)?;
+            }
+
+            Self::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => {
+                state.multiply_add(source, offset, factor)?;
+            }
+
+            Self::AddByteAt { offset, delta } => {
+                state.add_data_at(offset, delta)?;
             }
 
-            Self::QuickDecrementByte(ofx) => {
-                state.memory_buffer[state.data_ptr] -= ofx as u8;
+            Self::SetByteAt { offset, value } => {
+                state.set_data_at(offset, value)?;
+            }
+
+            Self::Scan(stride) => {
+                state.scan(stride)?;
             }
 
             Self::OutputByte => {
-                let output = &[state.memory_buffer[state.data_ptr]][..];
-                stdout
-                    .write_all(output)
-                    .expect("failed to read from std_out");
+                state.output_byte(0)?;
+            }
+
+            Self::OutputByteAt(offset) => {
+                state.output_byte(offset)?;
+            }
+
+            Self::OutputConst(byte) => {
+                state.output.write_all(&[byte])?;
             }
 
             Self::InputByte => {
-                let mut input_byte = [0u8];
-                std::io::stdin()
-                    .read_exact(&mut input_byte)
-                    .expect("failed to read from std_in");
-                state.memory_buffer[state.data_ptr] = input_byte[0];
+                state.input_byte(0)?;
+            }
+
+            Self::InputByteAt(offset) => {
+                state.input_byte(offset)?;
             }
 
             Self::LoopOpen { close_location } => {
-                if state.data() == 0 {
+                if state.data() == C::ZERO {
                     state.instruction_ptr = close_location;
                 }
             }
             Self::LoopClose { open_location } => {
-                if state.data() != 0 {
-                    state.instruction_ptr = open_location - 1;
+                //the cell is already known to be non-zero, go straight back into the body
+                if state.data() != C::ZERO {
+                    state.instruction_ptr = open_location;
                 }
             }
             Self::NOP => { /* Do absolutely nothing */ }
         }
         //finally increment program counter
         state.instruction_ptr += 1;
+        Ok(())
     }
 
 }
@@ -0,0 +1,61 @@
+use super::*;
+
+/// A tape cell the [`Interpreter`] can run programs on. Implemented for `u8`, `u16`, `u32` and
+/// `u64`.
+///
+/// I/O always moves single bytes: `,` stores the byte it read zero-extended to the cell width and
+/// `.` writes the low 8 bits of the cell, so wider cells print the same characters as byte cells
+/// for any value that fits in a byte.
+pub trait Cell: Copy + Default + PartialEq + fmt::Debug + 'static {
+    const ZERO: Self;
+    const MAX: Self;
+    /// width of the cell in bits
+    const BITS: u32;
+
+    fn from_byte(byte: u8) -> Self;
+
+    /// the low 8 bits of the cell
+    fn to_byte(self) -> u8;
+
+    fn to_i128(self) -> i128;
+
+    /// `value` reduced modulo 2^`BITS`
+    fn from_i128_wrapping(value: i128) -> Self;
+
+    /// `value` if it fits in the cell, `None` otherwise
+    fn from_i128_checked(value: i128) -> Option<Self>;
+}
+
+macro_rules! impl_cell {
+    ($($cell:ty),*) => {
+        $(
+            impl Cell for $cell {
+                const ZERO: Self = 0;
+                const MAX: Self = <$cell>::MAX;
+                const BITS: u32 = <$cell>::BITS;
+
+                fn from_byte(byte: u8) -> Self {
+                    byte as Self
+                }
+
+                fn to_byte(self) -> u8 {
+                    self as u8
+                }
+
+                fn to_i128(self) -> i128 {
+                    self as i128
+                }
+
+                fn from_i128_wrapping(value: i128) -> Self {
+                    value as Self
+                }
+
+                fn from_i128_checked(value: i128) -> Option<Self> {
+                    Self::try_from(value).ok()
+                }
+            }
+        )*
+    };
+}
+
+impl_cell!(u8, u16, u32, u64);
@@ -0,0 +1,69 @@
+use super::*;
+
+/// Errors that stop a program while the [`Interpreter`] is running it
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// the instruction at `instruction` tried to move the data pointer to `data_ptr`,
+    /// which is outside of the tape. `data_ptr` counts from the cell the program started on
+    PointerOutOfBounds { instruction: usize, data_ptr: isize },
+    /// a growable tape needed more than `max_len` cells to satisfy the instruction at
+    /// `instruction`
+    TapeLimitExceeded { instruction: usize, max_len: usize },
+    /// `+` or `-` took the cell at `data_ptr` out of range while running with
+    /// [`CellOverflow::Trap`]. `data_ptr` counts from the cell the program started on
+    CellOverflow { instruction: usize, data_ptr: isize },
+    /// `,` hit the end of input while running with [`EofPolicy::Error`]
+    UnexpectedEof { instruction: usize },
+    /// reading from the input or writing to the output failed
+    Io(io::Error),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PointerOutOfBounds {
+                instruction,
+                data_ptr,
+            } => write!(
+                f,
+                "data pointer moved out of bounds to cell {} at instruction {}",
+                data_ptr, instruction
+            ),
+            Self::TapeLimitExceeded {
+                instruction,
+                max_len,
+            } => write!(
+                f,
+                "tape grew past its limit of {} cells at instruction {}",
+                max_len, instruction
+            ),
+            Self::CellOverflow {
+                instruction,
+                data_ptr,
+            } => write!(
+                f,
+                "cell {} overflowed at instruction {}",
+                data_ptr, instruction
+            ),
+            Self::UnexpectedEof { instruction } => {
+                write!(f, "unexpected end of input at instruction {}", instruction)
+            }
+            Self::Io(err) => write!(f, "i/o error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
@@ -0,0 +1,672 @@
+use super::*;
+use crate::backend::x86::{Assembler, Cond, Label, Mem, Reg, Width};
+use std::{ffi::c_void, marker::PhantomData};
+
+/// Steps a slice of JIT code may run for before handing control back to check the clock and
+/// flush the output, stretched to cover the whole program if it's longer
+const JIT_SLICE_LEN: u64 = 1 << 20;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const PROT_EXEC: i32 = 4;
+const MAP_PRIVATE: i32 = 2;
+const MAP_ANONYMOUS: i32 = 0x20;
+const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: i32) -> i32;
+    fn munmap(addr: *mut c_void, len: usize) -> i32;
+}
+
+/// Registers the generated code keeps its state in, all callee-saved so calls back into Rust
+/// leave them alone
+const TAPE_START: Reg = Reg::Rbx;
+const DATA_PTR: Reg = Reg::R12;
+const TAPE_END: Reg = Reg::R13;
+const FRAME: Reg = Reg::R14;
+const FUEL: Reg = Reg::R15;
+
+/// Why the generated code returned, along with [`Frame::exit_ip`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u64)]
+enum Exit {
+    /// ran off the end of the program
+    Halted,
+    /// not enough of the slice left for the block at `exit_ip`, nothing of it has run
+    OutOfSlice,
+    /// the instruction at `exit_ip` reaches off the tape, the interpreter runs it instead so it
+    /// can grow the tape or report the error
+    Bail,
+    /// a callback failed at `exit_ip`, see [`Frame::fault`]
+    Fault,
+}
+
+enum Fault {
+    Io(io::Error),
+    Eof,
+}
+
+/// What the generated code and the callbacks it makes share. The code only touches the fields
+/// up to `exit_ip`, at the offsets below
+#[repr(C)]
+struct Frame<'io, C: Cell> {
+    tape_start: *mut C,
+    tape_end: *mut C,
+    data_ptr: *mut C,
+    fuel: u64,
+    exit_ip: u64,
+    input: &'io mut dyn Read,
+    output: &'io mut dyn Write,
+    eof_policy: EofPolicy,
+    fault: Option<Fault>,
+}
+
+const TAPE_START_OFFSET: i32 = 0;
+const TAPE_END_OFFSET: i32 = 8;
+const DATA_PTR_OFFSET: i32 = 16;
+const FUEL_OFFSET: i32 = 24;
+const EXIT_IP_OFFSET: i32 = 32;
+
+extern "C" fn output_cell<C: Cell>(frame: &mut Frame<C>, cell: *const C) -> bool {
+    // SAFETY: the generated code only passes pointers it checked against the tape
+    let byte = unsafe { *cell }.to_byte();
+    output_byte(frame, byte as u64)
+}
+
+extern "C" fn output_byte<C: Cell>(frame: &mut Frame<C>, byte: u64) -> bool {
+    match frame.output.write_all(&[byte as u8]) {
+        Ok(()) => true,
+        Err(err) => {
+            frame.fault = Some(Fault::Io(err));
+            false
+        }
+    }
+}
+
+extern "C" fn input_cell<C: Cell>(frame: &mut Frame<C>, cell: *mut C) -> bool {
+    if let Err(err) = frame.output.flush() {
+        frame.fault = Some(Fault::Io(err));
+        return false;
+    }
+    let mut input_byte = [0u8];
+    let value = loop {
+        match frame.input.read(&mut input_byte) {
+            Ok(0) => match frame.eof_policy {
+                EofPolicy::Zero => break C::ZERO,
+                EofPolicy::Max => break C::MAX,
+                EofPolicy::Unchanged => return true,
+                EofPolicy::Error => {
+                    frame.fault = Some(Fault::Eof);
+                    return false;
+                }
+            },
+            Ok(_) => break C::from_byte(input_byte[0]),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => {
+                frame.fault = Some(Fault::Io(err));
+                return false;
+            }
+        }
+    };
+    // SAFETY: the generated code only passes pointers it checked against the tape
+    unsafe { *cell = value };
+    true
+}
+
+/// Machine code for a whole program, living in its own executable mapping.
+///
+/// Fuel is charged a block at a time, a block being the straight run of instructions between
+/// two places a jump can land, so the code only checks it once per block. Every instruction has
+/// an entry point that charges for the rest of its block, which is how a run that stopped
+/// anywhere picks back up
+pub(crate) struct JitProgram {
+    code: *mut u8,
+    len: usize,
+    /// where to jump in to start at each instruction, plus one for the end of the program
+    entries: Vec<usize>,
+}
+
+impl Drop for JitProgram {
+    fn drop(&mut self) {
+        // SAFETY: `code` is a mapping of `len` bytes that nothing else refers to
+        unsafe { munmap(self.code as *mut c_void, self.len) };
+    }
+}
+
+impl JitProgram {
+    pub fn compile<C: Cell>(code: &[ByteCode]) -> io::Result<Self> {
+        let machine_code = CodeGen::<C>::new(code).generate();
+        let len = machine_code.code.len();
+        // SAFETY: a fresh anonymous mapping, filled in and made executable before anyone
+        // gets to call into it
+        unsafe {
+            let map = mmap(
+                std::ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_PRIVATE | MAP_ANONYMOUS,
+                -1,
+                0,
+            );
+            if map == MAP_FAILED {
+                return Err(io::Error::last_os_error());
+            }
+            let program = Self {
+                code: map as *mut u8,
+                len,
+                entries: machine_code.entries,
+            };
+            std::ptr::copy_nonoverlapping(machine_code.code.as_ptr(), program.code, len);
+            if mprotect(map, len, PROT_READ | PROT_EXEC) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(program)
+        }
+    }
+
+    /// runs the program from instruction `ip` until it exits
+    ///
+    /// # Safety
+    /// the frame's tape pointers have to describe a live tape with the data pointer on it, and
+    /// the program has to have been compiled for cells of type `C`
+    unsafe fn enter<C: Cell>(&self, frame: &mut Frame<C>, ip: usize) -> Exit {
+        let function: unsafe extern "C" fn(*mut Frame<C>, *const u8) -> Exit =
+            std::mem::transmute(self.code);
+        function(frame, self.code.add(self.entries[ip]))
+    }
+}
+
+struct MachineCode {
+    code: Vec<u8>,
+    entries: Vec<usize>,
+}
+
+/// An exit taken off the hot path, emitted after the rest of the code
+struct ColdExit {
+    label: Label,
+    exit: Exit,
+    ip: usize,
+    /// fuel charged up front for instructions that won't run now
+    refund: u64,
+}
+
+struct CodeGen<'a, C: Cell> {
+    asm: Assembler,
+    code: &'a [ByteCode],
+    width: Width,
+    /// the start of each instruction's code, plus one for the end of the program
+    labels: Vec<Label>,
+    /// whether a jump can land on each instruction, starting a block
+    block_starts: Vec<bool>,
+    /// how many instructions are left in the block from each instruction on
+    block_rest: Vec<u64>,
+    cold_exits: Vec<ColdExit>,
+    exit: Label,
+    cell: PhantomData<C>,
+}
+
+impl<'a, C: Cell> CodeGen<'a, C> {
+    fn new(code: &'a [ByteCode]) -> Self {
+        let mut block_starts = vec![false; code.len() + 1];
+        block_starts[0] = true;
+        block_starts[code.len()] = true;
+        for (idx, inst) in code.iter().enumerate() {
+            if let ByteCode::LoopOpen { .. } | ByteCode::LoopClose { .. } = inst {
+                block_starts[idx + 1] = true;
+            }
+        }
+        let mut block_rest = vec![0; code.len() + 1];
+        for idx in (0..code.len()).rev() {
+            block_rest[idx] = 1 + if block_starts[idx + 1] {
+                0
+            } else {
+                block_rest[idx + 1]
+            };
+        }
+
+        let mut asm = Assembler::new();
+        let labels = (0..=code.len()).map(|_| asm.new_label()).collect();
+        let exit = asm.new_label();
+        Self {
+            asm,
+            code,
+            width: Width::of_bits(C::BITS),
+            labels,
+            block_starts,
+            block_rest,
+            cold_exits: Vec::new(),
+            exit,
+            cell: PhantomData,
+        }
+    }
+
+    fn generate(mut self) -> MachineCode {
+        //extern "C" fn(frame, entry) -> Exit
+        let asm = &mut self.asm;
+        for reg in [Reg::Rbx, Reg::R12, Reg::R13, Reg::R14, Reg::R15] {
+            asm.push(reg);
+        }
+        asm.mov(FRAME, Reg::Rdi);
+        asm.load(TAPE_START, Mem::at(FRAME, TAPE_START_OFFSET));
+        asm.load(TAPE_END, Mem::at(FRAME, TAPE_END_OFFSET));
+        asm.load(DATA_PTR, Mem::at(FRAME, DATA_PTR_OFFSET));
+        asm.load(FUEL, Mem::at(FRAME, FUEL_OFFSET));
+        asm.jmp_reg(Reg::Rsi);
+
+        for ip in 0..self.code.len() {
+            self.asm.bind(self.labels[ip]);
+            if self.block_starts[ip] {
+                self.charge(ip);
+            }
+            self.instruction(ip);
+        }
+        let end = self.code.len();
+        self.asm.bind(self.labels[end]);
+        self.exit_with(Exit::Halted, end);
+
+        self.asm.bind(self.exit);
+        let asm = &mut self.asm;
+        asm.store(Mem::at(FRAME, EXIT_IP_OFFSET), Reg::Rcx);
+        asm.store(Mem::at(FRAME, DATA_PTR_OFFSET), DATA_PTR);
+        asm.store(Mem::at(FRAME, FUEL_OFFSET), FUEL);
+        for reg in [Reg::R15, Reg::R14, Reg::R13, Reg::R12, Reg::Rbx] {
+            asm.pop(reg);
+        }
+        asm.ret();
+
+        //instructions in the middle of a block need their own way in that pays for the rest
+        //of the block, the ones starting a block already do
+        let mut entries = Vec::with_capacity(self.labels.len());
+        let mut entry_labels = Vec::with_capacity(self.labels.len());
+        for ip in 0..=self.code.len() {
+            if self.block_starts[ip] {
+                entry_labels.push(self.labels[ip]);
+            } else {
+                let entry = self.asm.new_label();
+                self.asm.bind(entry);
+                self.charge(ip);
+                self.asm.jmp(self.labels[ip]);
+                entry_labels.push(entry);
+            }
+        }
+
+        for cold in std::mem::take(&mut self.cold_exits) {
+            self.asm.bind(cold.label);
+            if cold.refund > 0 {
+                self.asm.add_imm(FUEL, cold.refund as i32);
+            }
+            self.exit_with(cold.exit, cold.ip);
+        }
+
+        for label in entry_labels {
+            entries.push(self.asm.offset(label).expect("every entry is bound"));
+        }
+        MachineCode {
+            code: self.asm.finish(),
+            entries,
+        }
+    }
+
+    fn exit_with(&mut self, exit: Exit, ip: usize) {
+        self.asm.mov_imm(Reg::Rax, exit as u64);
+        self.asm.mov_imm(Reg::Rcx, ip as u64);
+        self.asm.jmp(self.exit);
+    }
+
+    /// a label for an exit that runs when it's jumped to
+    fn cold_exit(&mut self, exit: Exit, ip: usize, refund: u64) -> Label {
+        let label = self.asm.new_label();
+        self.cold_exits.push(ColdExit {
+            label,
+            exit,
+            ip,
+            refund,
+        });
+        label
+    }
+
+    /// takes the fuel for the rest of the block from the slice, leaving it untouched and
+    /// exiting if there isn't enough
+    fn charge(&mut self, ip: usize) {
+        let cost = self.block_rest[ip] as i32;
+        let out_of_slice = self.cold_exit(Exit::OutOfSlice, ip, 0);
+        self.asm.cmp_imm(FUEL, cost);
+        self.asm.jcc(Cond::Below, out_of_slice);
+        self.asm.sub_imm(FUEL, cost);
+    }
+
+    /// an exit that hands the instruction at `ip` over to the interpreter
+    fn bail(&mut self, ip: usize) -> Label {
+        self.cold_exit(Exit::Bail, ip, self.block_rest[ip])
+    }
+
+    /// the byte distance of `offset` cells, `None` if it doesn't fit a displacement
+    fn displacement(&self, offset: isize) -> Option<i32> {
+        let bytes = (offset as i64).checked_mul(self.width.bytes())?;
+        i32::try_from(bytes).ok()
+    }
+
+    /// whether every cell the instruction reaches for is close enough for a displacement
+    fn addressable(&self, inst: ByteCode) -> bool {
+        let offsets = match inst {
+            ByteCode::MoveDataPtr(offset)
+            | ByteCode::AddByteAt { offset, .. }
+            | ByteCode::SetByteAt { offset, .. }
+            | ByteCode::Scan(offset)
+            | ByteCode::OutputByteAt(offset)
+            | ByteCode::InputByteAt(offset) => [offset, 0],
+            ByteCode::MultiplyAdd { source, offset, .. } => [source, offset],
+            _ => [0, 0],
+        };
+        offsets
+            .iter()
+            .all(|&offset| self.displacement(offset).is_some())
+    }
+
+    /// puts the address of the cell `offset` away in `rdx`, bailing if it's off the tape
+    fn checked_address(&mut self, offset: isize, ip: usize) -> Mem {
+        if offset == 0 {
+            return Mem::at(DATA_PTR, 0);
+        }
+        let disp = self
+            .displacement(offset)
+            .expect("instructions are checked to be addressable");
+        let bail = self.bail(ip);
+        self.asm.lea(Reg::Rdx, Mem::at(DATA_PTR, disp));
+        self.asm.cmp(Reg::Rdx, TAPE_START);
+        self.asm.jcc(Cond::Below, bail);
+        self.asm.cmp(Reg::Rdx, TAPE_END);
+        self.asm.jcc(Cond::AboveOrEqual, bail);
+        Mem::at(Reg::Rdx, 0)
+    }
+
+    fn truncate(&self, value: isize) -> u64 {
+        self.width.truncate(value as i128)
+    }
+
+    fn instruction(&mut self, ip: usize) {
+        let width = self.width;
+        let inst = self.code[ip];
+        //offsets too far away for a displacement are left to the interpreter
+        if !self.addressable(inst) {
+            let bail = self.bail(ip);
+            self.asm.jmp(bail);
+            return;
+        }
+        match inst {
+            ByteCode::NOP => {}
+            ByteCode::IncrementDataPtr => self.move_data_ptr(1, ip),
+            ByteCode::DecrementDataPtr => self.move_data_ptr(-1, ip),
+            ByteCode::MoveDataPtr(amount) => self.move_data_ptr(amount, ip),
+            ByteCode::IncrementByte => self.asm.add_cell(width, Mem::at(DATA_PTR, 0), 1),
+            ByteCode::DecrementByte => {
+                let value = self.truncate(-1);
+                self.asm.add_cell(width, Mem::at(DATA_PTR, 0), value)
+            }
+            ByteCode::AddByte(delta) => {
+                let value = self.truncate(delta);
+                self.asm.add_cell(width, Mem::at(DATA_PTR, 0), value)
+            }
+            ByteCode::AddByteAt { offset, delta } => {
+                let mem = self.checked_address(offset, ip);
+                let value = self.truncate(delta);
+                self.asm.add_cell(width, mem, value);
+            }
+            ByteCode::SetByte(value) => {
+                let value = self.truncate(value);
+                self.asm.set_cell(width, Mem::at(DATA_PTR, 0), value)
+            }
+            ByteCode::SetByteAt { offset, value } => {
+                let mem = self.checked_address(offset, ip);
+                let value = self.truncate(value);
+                self.asm.set_cell(width, mem, value);
+            }
+            ByteCode::MultiplyAdd {
+                source,
+                offset,
+                factor,
+            } => {
+                let skip = self.asm.new_label();
+                let source = self.checked_address(source, ip);
+                self.asm.load_cell(width, Reg::Rax, source);
+                self.asm.test(Reg::Rax);
+                self.asm.jcc(Cond::Equal, skip);
+                let target = self.checked_address(offset, ip);
+                let factor = self.truncate(factor);
+                self.asm.mov_imm(Reg::Rcx, factor);
+                self.asm.imul(Reg::Rax, Reg::Rcx);
+                self.asm.add_cell_reg(width, target, Reg::Rax);
+                self.asm.bind(skip);
+            }
+            ByteCode::Scan(stride) => {
+                let (top, done) = (self.asm.new_label(), self.asm.new_label());
+                self.asm.bind(top);
+                self.asm.cmp_cell_zero(width, Mem::at(DATA_PTR, 0));
+                self.asm.jcc(Cond::Equal, done);
+                self.move_data_ptr(stride, ip);
+                self.asm.jmp(top);
+                self.asm.bind(done);
+            }
+            ByteCode::OutputByte => self.call_with_cell(output_cell::<C> as *const (), 0, ip),
+            ByteCode::OutputByteAt(offset) => {
+                self.call_with_cell(output_cell::<C> as *const (), offset, ip)
+            }
+            ByteCode::OutputConst(byte) => {
+                self.asm.mov_imm(Reg::Rsi, byte as u64);
+                self.call(output_byte::<C> as *const (), ip);
+            }
+            ByteCode::InputByte => self.call_with_cell(input_cell::<C> as *const (), 0, ip),
+            ByteCode::InputByteAt(offset) => {
+                self.call_with_cell(input_cell::<C> as *const (), offset, ip)
+            }
+            ByteCode::LoopOpen { close_location } => {
+                self.asm.cmp_cell_zero(width, Mem::at(DATA_PTR, 0));
+                self.asm.jcc(Cond::Equal, self.labels[close_location + 1]);
+            }
+            ByteCode::LoopClose { open_location } => {
+                self.asm.cmp_cell_zero(width, Mem::at(DATA_PTR, 0));
+                self.asm.jcc(Cond::NotEqual, self.labels[open_location + 1]);
+            }
+        }
+    }
+
+    /// moves the data pointer, checking the new position before committing to it
+    fn move_data_ptr(&mut self, amount: isize, ip: usize) {
+        let mem = self.checked_address(amount, ip);
+        if amount != 0 {
+            self.asm.mov(DATA_PTR, mem.base);
+        }
+    }
+
+    /// calls `callback(frame, &cell)` for the cell `offset` away
+    fn call_with_cell(&mut self, callback: *const (), offset: isize, ip: usize) {
+        let mem = self.checked_address(offset, ip);
+        self.asm.mov(Reg::Rsi, mem.base);
+        self.call(callback, ip);
+    }
+
+    /// calls `callback(frame, rsi)`, exiting with [`Exit::Fault`] if it returns `false`
+    fn call(&mut self, callback: *const (), ip: usize) {
+        let fault = self.cold_exit(Exit::Fault, ip, self.block_rest[ip] - 1);
+        self.asm.mov(Reg::Rdi, FRAME);
+        self.asm.mov_imm(Reg::Rax, callback as u64);
+        self.asm.call(Reg::Rax);
+        self.asm.test_al();
+        self.asm.jcc(Cond::Equal, fault);
+    }
+}
+
+impl<'inst, 'mem, 'io, C: Cell> Interpreter<'inst, 'mem, 'io, C> {
+    /// runs the program as machine code, handing single instructions to the interpreter
+    /// whenever they need the tape to grow or fail. Falls back on interpreting the whole
+    /// program if the code can't be mapped
+    pub(super) fn execute_jit(&mut self) -> Result<RunOutcome, RuntimeError> {
+        let program = match self.jit.take() {
+            Some(program) => program,
+            None => match JitProgram::compile::<C>(self.instruction_buffer) {
+                Ok(program) => program,
+                Err(_) => return self.execute_instructions(),
+            },
+        };
+        let outcome = self.execute_jit_program(&program);
+        self.jit = Some(program);
+        outcome
+    }
+
+    fn execute_jit_program(&mut self, program: &JitProgram) -> Result<RunOutcome, RuntimeError> {
+        let slice_len = JIT_SLICE_LEN.max(self.instruction_buffer.len() as u64 + 1);
+        let mut last_flush = Instant::now();
+        while self.instruction_pointer_in_bounds() {
+            let slice = self.fuel.map_or(slice_len, |fuel| fuel.min(slice_len));
+            let cells = self.memory.cells_mut().as_mut_ptr_range();
+            let mut frame = Frame {
+                tape_start: cells.start,
+                tape_end: cells.end,
+                // SAFETY: `run` makes sure the data pointer starts on the tape and everything
+                // that moves it keeps it there
+                data_ptr: unsafe { cells.start.add(self.data_ptr) },
+                fuel: slice,
+                exit_ip: 0,
+                input: &mut *self.input,
+                output: &mut *self.output,
+                eof_policy: self.eof_policy,
+                fault: None,
+            };
+            // SAFETY: the frame describes the tape as it is, nothing else touches it until the
+            // code returns
+            let exit = unsafe { program.enter(&mut frame, self.instruction_ptr) };
+            // SAFETY: the code never moves the data pointer off the tape
+            self.data_ptr = unsafe { frame.data_ptr.offset_from(cells.start) } as usize;
+            self.instruction_ptr = frame.exit_ip as usize;
+            if let Some(fuel) = self.fuel {
+                self.fuel = Some(fuel - (slice - frame.fuel));
+            }
+
+            match exit {
+                Exit::Halted => break,
+                Exit::Fault => {
+                    return Err(match frame.fault {
+                        Some(Fault::Io(err)) => RuntimeError::Io(err),
+                        Some(Fault::Eof) | None => RuntimeError::UnexpectedEof {
+                            instruction: self.instruction_ptr,
+                        },
+                    })
+                }
+                Exit::Bail => {
+                    if !self.step()? {
+                        return Ok(RunOutcome::OutOfFuel);
+                    }
+                }
+                //the fuel ran out in the middle of the block, finish up in the interpreter so it
+                //stops on exactly the right instruction
+                Exit::OutOfSlice if slice < slice_len => return self.execute_instructions(),
+                Exit::OutOfSlice => {}
+            }
+
+            let now = Instant::now();
+            if self.deadline.is_some_and(|deadline| now >= deadline) {
+                return Ok(RunOutcome::TimedOut);
+            }
+            if now - last_flush > FLUSH_INTERVAL {
+                last_flush = now;
+                self.output.flush()?;
+            }
+        }
+        Ok(RunOutcome::Halted)
+    }
+}
+
+#[test]
+fn jit_matches_the_interpreter() {
+    let run = |source: &str, engine, input: &[u8]| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::<u16>::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_engine(engine)
+            .with_input(input)
+            .with_output(&mut output)
+            .with_eof_policy(EofPolicy::Max);
+        let outcome = interpreter.run().unwrap();
+        let memory = interpreter.memory().to_vec();
+        drop(interpreter);
+        (outcome, memory, output)
+    };
+    let programs = [
+        "++++++++[>++++[>++>+++>+++>+<<<<-]>+>+>->>+[<]<-]>>.>---.+++++++..+++.>>.<-.<.+++.------.",
+        ",[>++++<-]>[>+>++<<-]>>[-]<.<[>]>>>,.,.--[<]",
+        "-->+[-<+>]<.>>>>+++[<<<]<+++.",
+    ];
+    for source in programs {
+        let expected = run(source, Engine::Interpreter, b"a");
+        assert_eq!(run(source, Engine::Jit, b"a"), expected, "{}", source);
+    }
+}
+
+#[test]
+fn jit_stops_on_the_same_instruction() {
+    let source = "++[>+++[>++<-]<-]>>[<+>-]<.";
+    let run = |engine, fuel| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut output = Vec::new();
+        let mut interpreter = Interpreter::<u8>::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_engine(engine)
+            .with_output(&mut output)
+            .with_fuel(fuel);
+        let mut stops = Vec::new();
+        while interpreter.run().unwrap() == RunOutcome::OutOfFuel {
+            stops.push((
+                interpreter.instruction_ptr,
+                interpreter.memory()[..4].to_vec(),
+            ));
+            interpreter.add_fuel(fuel);
+        }
+        stops.push((
+            interpreter.instruction_ptr,
+            interpreter.memory()[..4].to_vec(),
+        ));
+        drop(interpreter);
+        (stops, output)
+    };
+    for fuel in 1..8 {
+        assert_eq!(run(Engine::Jit, fuel), run(Engine::Interpreter, fuel));
+    }
+}
+
+#[test]
+fn jit_hands_tape_edges_to_the_interpreter() {
+    let run = |source, growth| {
+        let mut bytecode = Compiler::compile(source).unwrap();
+        let mut interpreter = Interpreter::<u8>::new()
+            .with_instruction_buffer(&mut bytecode)
+            .with_engine(Engine::Jit)
+            .with_growable_memory(growth);
+        let result = interpreter.run();
+        result.map(|_| {
+            interpreter
+                .memory()
+                .iter()
+                .filter(|&&cell| cell != 0)
+                .count()
+        })
+    };
+    let far_right = format!("+{}+[<]", ">".repeat(5000));
+    assert_eq!(run(&far_right, TapeGrowth::Right).unwrap(), 2);
+    assert_eq!(run("+[<+<<]", TapeGrowth::Both).unwrap(), 2);
+    assert!(matches!(
+        run("+>+<<", TapeGrowth::Right),
+        Err(RuntimeError::PointerOutOfBounds {
+            instruction: 2,
+            data_ptr: -1
+        })
+    ));
+}
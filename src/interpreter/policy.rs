@@ -0,0 +1,54 @@
+use super::*;
+
+/// What `,` does once the input has run dry
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum EofPolicy {
+    /// store 0 in the current cell
+    #[default]
+    Zero,
+    /// store the largest cell value (255 for byte cells, -1 in two's complement)
+    Max,
+    /// leave the current cell as it was
+    Unchanged,
+    /// stop the program with [`RuntimeError::UnexpectedEof`](super::RuntimeError::UnexpectedEof)
+    Error,
+}
+
+/// How [`Interpreter::run`](super::Interpreter::run) executes the program
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Engine {
+    /// dispatch on one instruction at a time
+    #[default]
+    Interpreter,
+    /// compile the program to x86-64 machine code on its first run. Only available on x86-64
+    /// Linux with [`CellOverflow::Wrapping`], anything else quietly runs on the interpreter
+    Jit,
+}
+
+/// What happens when `+` or `-` pushes a cell past its largest or smallest value
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum CellOverflow {
+    /// wrap around, 255 + 1 = 0 and 0 - 1 = 255
+    #[default]
+    Wrapping,
+    /// clamp to the cell's range, 255 + 1 = 255 and 0 - 1 = 0
+    Saturating,
+    /// stop the program with [`RuntimeError::CellOverflow`](super::RuntimeError::CellOverflow)
+    Trap,
+}
+
+impl CellOverflow {
+    /// adds `delta` to `value`, `None` means the cell overflowed under [`CellOverflow::Trap`]
+    pub fn apply<C: Cell>(self, value: C, delta: i128) -> Option<C> {
+        let result = value.to_i128() + delta;
+        match self {
+            Self::Wrapping => Some(C::from_i128_wrapping(result)),
+            Self::Saturating => Some(C::from_i128_checked(result).unwrap_or(if result < 0 {
+                C::ZERO
+            } else {
+                C::MAX
+            })),
+            Self::Trap => C::from_i128_checked(result),
+        }
+    }
+}
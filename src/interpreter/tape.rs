@@ -0,0 +1,128 @@
+use super::*;
+
+/// Cells an owned tape starts out with before it needs to grow
+const INITIAL_TAPE_LEN: usize = 1024;
+
+/// Largest an owned tape grows to unless told otherwise
+pub const DEFAULT_MAX_TAPE_LEN: usize = 1 << 24;
+
+/// Directions an owned tape is allowed to grow in
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub enum TapeGrowth {
+    /// the tape starts at the leftmost cell and grows to the right
+    #[default]
+    Right,
+    /// the tape grows in both directions, moving left of the starting cell is allowed
+    Both,
+}
+
+/// Why a tape couldn't make room for a cell
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum TapeLimit {
+    /// the tape can't grow in that direction at all
+    Edge,
+    /// the tape would grow past its maximum length
+    MaxLen(usize),
+}
+
+enum Storage<'mem, C> {
+    Borrowed(&'mem mut [C]),
+    Owned(Vec<C>),
+}
+
+/// The interpreter's memory, either a caller-provided slice of fixed size or an owned buffer
+/// that grows on demand
+pub(crate) struct Tape<'mem, C: Cell> {
+    storage: Storage<'mem, C>,
+    growth: Option<TapeGrowth>,
+    max_len: usize,
+    /// index of the program's starting cell, only moves when the tape grows to the left
+    origin: usize,
+}
+
+impl<'mem, C: Cell> Tape<'mem, C> {
+    pub fn fixed(cells: &'mem mut [C], max_len: usize) -> Self {
+        Self {
+            storage: Storage::Borrowed(cells),
+            growth: None,
+            max_len,
+            origin: 0,
+        }
+    }
+
+    pub fn growable(growth: TapeGrowth, max_len: usize) -> Self {
+        Self {
+            storage: Storage::Owned(vec![C::ZERO; INITIAL_TAPE_LEN.min(max_len)]),
+            growth: Some(growth),
+            max_len,
+            origin: 0,
+        }
+    }
+
+    pub fn max_len(&self) -> usize {
+        self.max_len
+    }
+
+    /// caps how far the tape may grow, owned tapes that are already longer get cut down
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+        if let Storage::Owned(cells) = &mut self.storage {
+            cells.truncate(max_len);
+        }
+    }
+
+    pub fn cells(&self) -> &[C] {
+        match &self.storage {
+            Storage::Borrowed(cells) => cells,
+            Storage::Owned(cells) => cells,
+        }
+    }
+
+    pub fn cells_mut(&mut self) -> &mut [C] {
+        match &mut self.storage {
+            Storage::Borrowed(cells) => cells,
+            Storage::Owned(cells) => cells,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.cells().len()
+    }
+
+    /// converts a storage index into a position relative to the starting cell
+    pub fn position(&self, index: isize) -> isize {
+        index - self.origin as isize
+    }
+
+    /// grows the tape until storage index `index` exists. Returns how far existing cells
+    /// were shifted to the right, which is non-zero only when growing to the left
+    pub fn grow_to_fit(&mut self, index: isize) -> Result<usize, TapeLimit> {
+        let len = self.len();
+        let growth = self.growth.ok_or(TapeLimit::Edge)?;
+        let max_len = self.max_len;
+        let Storage::Owned(cells) = &mut self.storage else {
+            return Err(TapeLimit::Edge);
+        };
+
+        if index < 0 {
+            if growth != TapeGrowth::Both {
+                return Err(TapeLimit::Edge);
+            }
+            let needed = index.unsigned_abs();
+            if needed > max_len.saturating_sub(len) {
+                return Err(TapeLimit::MaxLen(max_len));
+            }
+            let shift = needed.max(len).min(max_len - len);
+            cells.splice(0..0, std::iter::repeat_n(C::ZERO, shift));
+            self.origin += shift;
+            Ok(shift)
+        } else {
+            let needed = index as usize + 1;
+            if needed > max_len {
+                return Err(TapeLimit::MaxLen(max_len));
+            }
+            cells.resize(needed.max(len * 2).min(max_len), C::ZERO);
+            Ok(0)
+        }
+    }
+}
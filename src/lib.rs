@@ -1,9 +1,12 @@
 use std::{
+    fmt,
     io::{self, BufWriter, Read, Write},
-    time::Instant,
+    time::{Duration, Instant},
 };
 mod interpreter;
 mod compiler;
+mod backend;
+pub use backend::*;
 pub use compiler::*;
 pub use interpreter::*;
 
@@ -1,3 +1,605 @@
-fn main() {
-    /* CLI unimplemented */
+use brainfrusk::*;
+use std::{
+    env, fs,
+    io::{self, Write},
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+
+const USAGE: &str = "usage: brainfrusk <command> [options] <file>
+
+commands:
+    run <file>        compile and execute a program
+    compile <file>    check a program and report what each optimization pass did, or
+                      translate it with --emit
+    disasm <file>     print the compiled bytecode next to its source location
+    bench <file>      time compilation and execution of a program
+
+options:
+    -O<level>         optimization level from 0 to 3 (default: 2)
+    --passes <list>   comma separated passes to run in place of the level's, out of
+                      fold-runs, dead-loops, clear-loops, multiply-loops, scan-loops,
+                      pointer-offsets, constant-folding and trailing-code
+    --fold-budget <n> steps constant-folding may run the program for at compile time
+                      (default: 1048576)
+    --comments <kind> what characters other than commands mean: standard (comments),
+                      hash (also '#' comments out the rest of the line) or strict
+                      (rejected) (default: standard)
+    --emit <target>   have compile write the program as c or rust source, as x86-64
+                      assembly for the gnu assembler (asm) or nasm, or as a static linux
+                      x86-64 elf executable, instead of reporting. Emitted programs
+                      have a fixed tape and wrapping cells
+    -o <file>         where --emit writes to (default: stdout)
+    --tape <mode>     fixed, grow (to the right) or grow-both (default: fixed)
+    --tape-size <n>   number of cells on a fixed tape (default: 30000) or the most a
+                      growing tape may reach (default: 16777216)
+    --cell-width <n>  bits per cell: 8, 16, 32 or 64 (default: 8)
+    --eof <policy>    what ',' stores at end of input: zero, max, unchanged or error
+                      (default: zero)
+    --overflow <mode> what '+' and '-' do at the edge of a cell: wrap, saturate or trap
+                      (default: wrap)
+    --engine <kind>   interpreter, or jit to run as x86-64 machine code where supported
+                      (default: interpreter)
+    --limit <n>       stop after executing n instructions
+    --timeout <secs>  stop after running for this many seconds
+    -h, --help        print this message";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Command {
+    Run,
+    Compile,
+    Disasm,
+    Bench,
+}
+
+/// What `compile --emit` translates the program into
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Emit {
+    C,
+    /// a standalone `main.rs`
+    Rust,
+    /// a static Linux x86-64 executable
+    Elf,
+    /// the executable's code as annotated assembly
+    Asm(AsmSyntax),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Options {
+    command: Command,
+    path: String,
+    optimization_level: u8,
+    passes: Option<Vec<Pass>>,
+    comment_syntax: CommentSyntax,
+    fold_budget: Option<u64>,
+    emit: Option<Emit>,
+    output: Option<String>,
+    tape: Option<TapeGrowth>,
+    tape_size: Option<usize>,
+    cell_width: u32,
+    eof_policy: EofPolicy,
+    cell_overflow: CellOverflow,
+    engine: Engine,
+    instruction_limit: Option<u64>,
+    timeout: Option<Duration>,
+}
+
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, String> {
+        let command = match args.next().as_deref() {
+            Some("run") => Command::Run,
+            Some("compile") => Command::Compile,
+            Some("disasm") => Command::Disasm,
+            Some("bench") => Command::Bench,
+            Some(unknown) => return Err(format!("unknown command '{}'", unknown)),
+            None => return Err(String::from("missing command")),
+        };
+
+        let mut path = None;
+        let mut optimization_level = 2;
+        let mut passes = None;
+        let mut comment_syntax = CommentSyntax::default();
+        let mut fold_budget = None;
+        let mut emit = None;
+        let mut output = None;
+        let mut tape = None;
+        let mut tape_size = None;
+        let mut cell_width = 8;
+        let mut eof_policy = EofPolicy::default();
+        let mut cell_overflow = CellOverflow::default();
+        let mut engine = Engine::default();
+        let mut instruction_limit = None;
+        let mut timeout = None;
+        while let Some(arg) = args.next() {
+            if let Some(level) = arg.strip_prefix("-O") {
+                optimization_level = match level {
+                    "0" => 0,
+                    "1" => 1,
+                    "2" => 2,
+                    "3" => 3,
+                    _ => return Err(format!("invalid optimization level '{}'", level)),
+                };
+                continue;
+            }
+            match arg.as_str() {
+                "--passes" => {
+                    let list = args
+                        .next()
+                        .ok_or_else(|| format!("{} expects a value", arg))?;
+                    passes = Some(
+                        list.split(',')
+                            .filter(|name| !name.is_empty())
+                            .map(str::parse)
+                            .collect::<Result<_, _>>()?,
+                    );
+                }
+                "--fold-budget" => fold_budget = Some(Self::parse_value(&arg, args.next())?),
+                "--comments" => {
+                    comment_syntax = match args.next().as_deref() {
+                        Some("standard") => CommentSyntax::Standard,
+                        Some("hash") => CommentSyntax::HashLineComments,
+                        Some("strict") => CommentSyntax::Strict,
+                        Some(kind) => return Err(format!("invalid comment syntax '{}'", kind)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    };
+                }
+                "--emit" => {
+                    emit = match args.next().as_deref() {
+                        Some("c") => Some(Emit::C),
+                        Some("rust") => Some(Emit::Rust),
+                        Some("elf") => Some(Emit::Elf),
+                        Some("asm") => Some(Emit::Asm(AsmSyntax::Att)),
+                        Some("nasm") => Some(Emit::Asm(AsmSyntax::Intel)),
+                        Some(target) => return Err(format!("unknown emit target '{}'", target)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    }
+                }
+                "-o" => output = Some(Self::parse_value(&arg, args.next())?),
+                "--tape" => {
+                    tape = match args.next().as_deref() {
+                        Some("fixed") => None,
+                        Some("grow") => Some(TapeGrowth::Right),
+                        Some("grow-both") => Some(TapeGrowth::Both),
+                        Some(mode) => return Err(format!("invalid tape mode '{}'", mode)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    };
+                }
+                "--tape-size" => {
+                    let size = Self::parse_value(&arg, args.next())?;
+                    if size == 0 {
+                        return Err(String::from("--tape-size must be at least 1"));
+                    }
+                    tape_size = Some(size);
+                }
+                "--cell-width" => {
+                    cell_width = Self::parse_value(&arg, args.next())?;
+                    if ![8, 16, 32, 64].contains(&cell_width) {
+                        return Err(format!("unsupported cell width '{}'", cell_width));
+                    }
+                }
+                "--eof" => {
+                    eof_policy = match args.next().as_deref() {
+                        Some("zero") => EofPolicy::Zero,
+                        Some("max") => EofPolicy::Max,
+                        Some("unchanged") => EofPolicy::Unchanged,
+                        Some("error") => EofPolicy::Error,
+                        Some(policy) => return Err(format!("invalid eof policy '{}'", policy)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    };
+                }
+                "--overflow" => {
+                    cell_overflow = match args.next().as_deref() {
+                        Some("wrap") => CellOverflow::Wrapping,
+                        Some("saturate") => CellOverflow::Saturating,
+                        Some("trap") => CellOverflow::Trap,
+                        Some(mode) => return Err(format!("invalid overflow mode '{}'", mode)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    };
+                }
+                "--engine" => {
+                    engine = match args.next().as_deref() {
+                        Some("interpreter") => Engine::Interpreter,
+                        Some("jit") => Engine::Jit,
+                        Some(engine) => return Err(format!("unknown engine '{}'", engine)),
+                        None => return Err(format!("{} expects a value", arg)),
+                    };
+                }
+                "--limit" => instruction_limit = Some(Self::parse_value(&arg, args.next())?),
+                "--timeout" => {
+                    let seconds = Self::parse_value(&arg, args.next())?;
+                    timeout = Some(
+                        Duration::try_from_secs_f64(seconds)
+                            .map_err(|_| format!("invalid timeout '{}'", seconds))?,
+                    );
+                }
+                _ if arg.starts_with('-') => return Err(format!("unknown option '{}'", arg)),
+                _ if path.is_some() => return Err(format!("unexpected argument '{}'", arg)),
+                _ => path = Some(arg),
+            }
+        }
+
+        if command != Command::Compile && (emit.is_some() || output.is_some()) {
+            return Err(String::from("--emit and -o only apply to compile"));
+        }
+        if output.is_some() && emit.is_none() {
+            return Err(String::from("-o needs --emit"));
+        }
+        if emit.is_some() && cell_overflow != CellOverflow::Wrapping {
+            return Err(String::from(
+                "emitted programs always wrap, --overflow can't be used",
+            ));
+        }
+        if emit.is_some() && tape.is_some() {
+            return Err(String::from(
+                "emitted programs have a fixed tape, --tape can't grow it",
+            ));
+        }
+
+        Ok(Self {
+            command,
+            path: path.ok_or_else(|| String::from("missing input file"))?,
+            optimization_level,
+            passes,
+            comment_syntax,
+            fold_budget,
+            emit,
+            output,
+            tape,
+            tape_size,
+            cell_width,
+            eof_policy,
+            cell_overflow,
+            engine,
+            instruction_limit,
+            timeout,
+        })
+    }
+
+    fn parse_value<T: std::str::FromStr>(flag: &str, value: Option<String>) -> Result<T, String> {
+        let value = value.ok_or_else(|| format!("{} expects a value", flag))?;
+        value
+            .parse()
+            .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+    }
+
+    fn compile(&self, source: &str) -> Result<CompileOutput, String> {
+        let compile_options = match &self.passes {
+            Some(passes) => CompileOptions::with_passes(passes.iter().copied()),
+            None => CompileOptions::level(self.optimization_level),
+        };
+        self.compile_with(source, compile_options)
+    }
+
+    fn compile_with(
+        &self,
+        source: &str,
+        compile_options: CompileOptions,
+    ) -> Result<CompileOutput, String> {
+        let mut compile_options = compile_options
+            .with_comment_syntax(self.comment_syntax)
+            .with_cell_overflow(self.cell_overflow);
+        if let Some(fold_budget) = self.fold_budget {
+            compile_options = compile_options.with_fold_budget(fold_budget);
+        }
+        Compiler::compile_with_options(source, &compile_options)
+            .map_err(|err| format!("{}: {}", self.path, err))
+    }
+}
+
+fn main() -> ExitCode {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.is_empty() || args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return ExitCode::SUCCESS;
+    }
+    let result = Options::parse(args.into_iter()).and_then(|options| execute(&options));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            eprintln!("brainfrusk: error: {}", message);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn execute(options: &Options) -> Result<(), String> {
+    let source = fs::read_to_string(&options.path)
+        .map_err(|err| format!("failed to read '{}': {}", options.path, err))?;
+
+    match options.command {
+        Command::Run => {
+            let mut bytecode = options.compile(&source)?.bytecode;
+            run(options, &mut bytecode)?;
+        }
+        Command::Compile => {
+            let output = options.compile(&source)?;
+            if let Some(target) = options.emit {
+                return emit(options, target, &output);
+            }
+            for stats in &output.statistics {
+                println!(
+                    "{:<16} {:>8} -> {:>8} instructions in {:?}",
+                    stats.pass.name(),
+                    stats.instructions_before,
+                    stats.instructions_after,
+                    stats.duration
+                );
+            }
+            let commands = options
+                .compile_with(&source, CompileOptions::level(0))?
+                .bytecode
+                .len();
+            println!(
+                "{}: {} commands compiled into {} instructions",
+                options.path,
+                commands,
+                output.bytecode.len()
+            );
+        }
+        Command::Disasm => {
+            let output = options.compile(&source)?;
+            let listing = output.bytecode.iter().zip(output.source_map.iter());
+            for (idx, (inst, span)) in listing.enumerate() {
+                println!("{:>6}  {:<12} {:?}", idx, span.to_string(), inst);
+            }
+        }
+        Command::Bench => {
+            let t0 = Instant::now();
+            let mut bytecode = options.compile(&source)?.bytecode;
+            let compile_time = t0.elapsed();
+
+            let t0 = Instant::now();
+            run(options, &mut bytecode)?;
+            let run_time = t0.elapsed();
+
+            eprintln!(
+                "compiled {} instructions in {:?}, ran in {:?}",
+                bytecode.len(),
+                compile_time,
+                run_time
+            );
+        }
+    }
+    Ok(())
+}
+
+fn emit(options: &Options, target: Emit, compiled: &CompileOutput) -> Result<(), String> {
+    let mut program = Vec::new();
+    let emitted = match options.cell_width {
+        8 => emit_with_cells::<u8>(options, target, compiled, &mut program),
+        16 => emit_with_cells::<u16>(options, target, compiled, &mut program),
+        32 => emit_with_cells::<u32>(options, target, compiled, &mut program),
+        _ => emit_with_cells::<u64>(options, target, compiled, &mut program),
+    };
+    emitted.map_err(|err| format!("{}: {}", options.path, err))?;
+    match &options.output {
+        Some(path) => fs::write(path, program)
+            .and_then(|()| make_executable(path, target))
+            .map_err(|err| format!("failed to write '{}': {}", path, err)),
+        None => io::stdout()
+            .write_all(&program)
+            .map_err(|err| format!("failed to write the program: {}", err)),
+    }
+}
+
+fn emit_with_cells<C: Cell>(
+    options: &Options,
+    target: Emit,
+    compiled: &CompileOutput,
+    output: &mut dyn Write,
+) -> io::Result<()> {
+    let tape_len = options.tape_size.unwrap_or(DEFAULT_TAPE_LEN);
+    let bytecode = &compiled.bytecode;
+    match target {
+        Emit::C => CBackend::<C>::new()
+            .with_tape_len(tape_len)
+            .with_eof_policy(options.eof_policy)
+            .emit(bytecode, output),
+        Emit::Rust => RustBackend::<C>::new()
+            .with_tape_len(tape_len)
+            .with_eof_policy(options.eof_policy)
+            .with_main()
+            .emit(bytecode, output),
+        Emit::Elf => ElfBackend::<C>::new()
+            .with_tape_len(tape_len)
+            .with_eof_policy(options.eof_policy)
+            .emit(bytecode, output),
+        Emit::Asm(syntax) => AsmBackend::<C>::new()
+            .with_tape_len(tape_len)
+            .with_eof_policy(options.eof_policy)
+            .with_syntax(syntax)
+            .with_source_map(compiled.source_map.clone())
+            .emit(bytecode, output),
+    }
+}
+
+/// sets the executable bits on native executables the way a linker would
+#[cfg(unix)]
+fn make_executable(path: &str, target: Emit) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    if target == Emit::Elf {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o755))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &str, _target: Emit) -> io::Result<()> {
+    Ok(())
+}
+
+fn run(options: &Options, bytecode: &mut [ByteCode]) -> Result<RunOutcome, String> {
+    match options.cell_width {
+        8 => run_with_cells::<u8>(options, bytecode),
+        16 => run_with_cells::<u16>(options, bytecode),
+        32 => run_with_cells::<u32>(options, bytecode),
+        _ => run_with_cells::<u64>(options, bytecode),
+    }
+}
+
+fn run_with_cells<C: Cell>(
+    options: &Options,
+    bytecode: &mut [ByteCode],
+) -> Result<RunOutcome, String> {
+    let mut memory = Vec::new();
+    let interpreter = Interpreter::new().with_instruction_buffer(bytecode);
+    let interpreter = match options.tape {
+        Some(growth) => interpreter
+            .with_growable_memory(growth)
+            .with_max_memory(options.tape_size.unwrap_or(DEFAULT_MAX_TAPE_LEN)),
+        None => {
+            memory.resize(options.tape_size.unwrap_or(30_000), C::ZERO);
+            interpreter.with_memory(&mut memory)
+        }
+    };
+    let mut interpreter = interpreter
+        .with_eof_policy(options.eof_policy)
+        .with_cell_overflow(options.cell_overflow)
+        .with_engine(options.engine);
+    if let Some(limit) = options.instruction_limit {
+        interpreter = interpreter.with_fuel(limit);
+    }
+    if let Some(timeout) = options.timeout {
+        interpreter = interpreter.with_timeout(timeout);
+    }
+    match interpreter.run() {
+        Ok(RunOutcome::Halted) => Ok(RunOutcome::Halted),
+        Ok(RunOutcome::OutOfFuel) => Err(format!(
+            "{}: stopped after executing {} instructions",
+            options.path,
+            options.instruction_limit.unwrap_or_default()
+        )),
+        Ok(RunOutcome::TimedOut) => Err(format!(
+            "{}: stopped after running for {:?}",
+            options.path,
+            options.timeout.unwrap_or_default()
+        )),
+        Err(err) => Err(format!("{}: {}", options.path, err)),
+    }
+}
+
+#[test]
+fn parses_command_and_flags() {
+    let args = [
+        "run",
+        "-O0",
+        "--tape",
+        "grow-both",
+        "--tape-size",
+        "64",
+        "--cell-width",
+        "16",
+        "--eof",
+        "max",
+        "--comments",
+        "strict",
+        "--engine",
+        "jit",
+        "--limit",
+        "1000",
+        "hello.bf",
+    ]
+    .map(String::from);
+    let options = Options::parse(args.into_iter()).unwrap();
+    assert_eq!(
+        options,
+        Options {
+            command: Command::Run,
+            path: String::from("hello.bf"),
+            optimization_level: 0,
+            passes: None,
+            comment_syntax: CommentSyntax::Strict,
+            fold_budget: None,
+            emit: None,
+            output: None,
+            tape: Some(TapeGrowth::Both),
+            tape_size: Some(64),
+            cell_width: 16,
+            eof_policy: EofPolicy::Max,
+            cell_overflow: CellOverflow::Wrapping,
+            engine: Engine::Jit,
+            instruction_limit: Some(1000),
+            timeout: None,
+        }
+    );
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    let parse = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string()));
+    assert!(parse(&["launch", "hello.bf"]).is_err());
+    assert!(parse(&["run"]).is_err());
+    assert!(parse(&["run", "--tape-size", "lots", "hello.bf"]).is_err());
+    assert!(parse(&["run", "-O7", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--eof", "maybe", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--overflow"]).is_err());
+    assert!(parse(&["run", "--tape", "infinite", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--timeout", "-1", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--cell-width", "12", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--comments", "c", "hello.bf"]).is_err());
+    assert!(parse(&["run", "--engine", "llvm", "hello.bf"]).is_err());
+    //flags that wouldn't do anything
+    assert!(parse(&["run", "--emit", "c", "-o", "hello.c", "hello.bf"]).is_err());
+    assert!(parse(&["bench", "-o", "hello.c", "hello.bf"]).is_err());
+    assert!(parse(&["compile", "-o", "hello.c", "hello.bf"]).is_err());
+    assert!(parse(&[
+        "compile",
+        "--emit",
+        "c",
+        "--overflow",
+        "saturate",
+        "hello.bf"
+    ])
+    .is_err());
+    assert!(parse(&["compile", "--emit", "elf", "--overflow", "trap", "hello.bf"]).is_err());
+    assert!(parse(&["compile", "--emit", "rust", "--tape", "grow", "hello.bf"]).is_err());
+    assert!(parse(&[
+        "compile",
+        "--emit",
+        "asm",
+        "--tape",
+        "grow-both",
+        "hello.bf"
+    ])
+    .is_err());
+    assert!(parse(&["compile", "--emit", "c", "--overflow", "wrap", "hello.bf"]).is_ok());
+    assert!(parse(&[
+        "compile",
+        "--overflow",
+        "trap",
+        "--tape",
+        "grow",
+        "hello.bf"
+    ])
+    .is_ok());
+}
+
+#[test]
+fn parses_optimization_passes() {
+    let parse = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string()));
+    let options = parse(&["compile", "-O3", "hello.bf"]).unwrap();
+    assert_eq!(options.optimization_level, 3);
+    let options = parse(&["run", "--passes", "scan-loops,fold-runs", "hello.bf"]).unwrap();
+    assert_eq!(options.passes, Some(vec![Pass::ScanLoops, Pass::FoldRuns]));
+    assert!(parse(&["run", "--passes", "fold-runs,inline", "hello.bf"]).is_err());
+    assert!(parse(&["run", "-O4", "hello.bf"]).is_err());
+    let options = parse(&["run", "--fold-budget", "500", "hello.bf"]).unwrap();
+    assert_eq!(options.fold_budget, Some(500));
+}
+
+#[test]
+fn parses_emit_target() {
+    let parse = |args: &[&str]| Options::parse(args.iter().map(|arg| arg.to_string()));
+    let options = parse(&["compile", "--emit", "c", "-o", "hello.c", "hello.bf"]).unwrap();
+    assert_eq!(options.emit, Some(Emit::C));
+    assert_eq!(options.output.as_deref(), Some("hello.c"));
+    let options = parse(&["compile", "--emit", "rust", "hello.bf"]).unwrap();
+    assert_eq!(options.emit, Some(Emit::Rust));
+    assert_eq!(options.output, None);
+    let options = parse(&["compile", "--emit", "elf", "-o", "hello", "hello.bf"]).unwrap();
+    assert_eq!(options.emit, Some(Emit::Elf));
+    let options = parse(&["compile", "--emit", "nasm", "hello.bf"]).unwrap();
+    assert_eq!(options.emit, Some(Emit::Asm(AsmSyntax::Intel)));
+    assert!(parse(&["compile", "--emit", "fortran", "hello.bf"]).is_err());
+    assert!(parse(&["compile", "--emit", "c", "-o"]).is_err());
 }